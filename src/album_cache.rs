@@ -0,0 +1,96 @@
+use crate::api::get_album_info;
+use crate::state;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use log::info;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::time::Duration;
+
+const ALBUM_CACHE_FILE: &str = "immich_album_cache.json";
+
+/// Locally cached asset IDs per album, so already-linked assets aren't sent again,
+/// and album IDs by name, so the album list isn't downloaded every run.
+#[derive(Default, Serialize, Deserialize)]
+pub struct AlbumCache {
+    albums: HashMap<String, CachedAlbum>,
+    #[serde(default)]
+    ids: HashMap<String, CachedId>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedId {
+    id: String,
+    resolved_at: DateTime<Utc>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct CachedAlbum {
+    /// Server `updatedAt` at the time the cache was last known to be complete
+    updated_at: String,
+    asset_ids: HashSet<String>,
+}
+
+impl AlbumCache {
+    pub fn load() -> Self {
+        File::open(state::path(ALBUM_CACHE_FILE))
+            .ok()
+            .and_then(|f| serde_json::from_reader(f).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if self.albums.is_empty() && self.ids.is_empty() && !state::path(ALBUM_CACHE_FILE).exists() {
+            return Ok(());
+        }
+        serde_json::to_writer(File::create(state::path(ALBUM_CACHE_FILE))?, self)?;
+        Ok(())
+    }
+
+    /// The ID the album name resolved to, if that was less than `ttl` ago.
+    pub fn album_id(&self, name: &str, ttl: Duration) -> Option<&str> {
+        let cached = self.ids.get(name)?;
+        let age = (Utc::now() - cached.resolved_at).to_std().unwrap_or_default();
+        (age < ttl).then_some(cached.id.as_str())
+    }
+
+    pub fn remember_album_id(&mut self, name: &str, id: &str) {
+        self.ids.insert(name.to_string(), CachedId { id: id.to_string(), resolved_at: Utc::now() });
+    }
+
+    /// Drops everything known about an album the server no longer has.
+    pub fn forget_album(&mut self, album_id: &str) {
+        self.albums.remove(album_id);
+        self.ids.retain(|_, cached| cached.id != album_id);
+    }
+
+    /// Returns the album's asset IDs, downloading the full list only when the
+    /// server reports the album changed since the cache was last refreshed.
+    pub async fn members(&mut self, client: &Client, base_url: &str, key: &str, album_id: &str) -> Result<&HashSet<String>> {
+        let summary = get_album_info(client, base_url, key, album_id, false).await?;
+        let stale = self.albums.get(album_id).is_none_or(|c| c.updated_at != summary.updated_at);
+        if stale {
+            info!("Refreshing cached album membership...");
+            let full = get_album_info(client, base_url, key, album_id, true).await?;
+            self.albums.insert(
+                album_id.to_string(),
+                CachedAlbum {
+                    updated_at: full.updated_at,
+                    asset_ids: full.assets.into_iter().map(|a| a.id).collect(),
+                },
+            );
+        }
+        Ok(&self.albums.entry(album_id.to_string()).or_default().asset_ids)
+    }
+
+    /// Records assets we just linked, then re-stamps the album so our own change
+    /// doesn't force a full refresh next run.
+    pub async fn record_added(&mut self, client: &Client, base_url: &str, key: &str, album_id: &str, asset_ids: &[String]) -> Result<()> {
+        let album = self.albums.entry(album_id.to_string()).or_default();
+        album.asset_ids.extend(asset_ids.iter().cloned());
+        album.updated_at = get_album_info(client, base_url, key, album_id, false).await?.updated_at;
+        Ok(())
+    }
+}
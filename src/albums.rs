@@ -0,0 +1,44 @@
+use crate::config::SourceFolder;
+use crate::metadata::taken_at;
+use chrono::{DateTime, Local};
+use std::fs;
+use std::path::Path;
+
+/// Placeholders album names may use, filled in from each file's capture date.
+const PLACEHOLDERS: &[(&str, &str)] = &[("{YYYY}", "%Y"), ("{YY}", "%y"), ("{MM}", "%m"), ("{DD}", "%d"), ("{MMMM}", "%B")];
+
+/// Whether `name` is a template like `Screenshots {YYYY}-{MM}` rather than a fixed name.
+pub fn is_template(name: &str) -> bool {
+    PLACEHOLDERS.iter().any(|(placeholder, _)| name.contains(placeholder))
+}
+
+/// Fills in the placeholders of `template` for `path`, dated by its capture date
+/// (EXIF, else the filename) or, failing that, its modification time.
+pub fn render(template: &str, path: &Path, date_patterns: &[String]) -> String {
+    if !is_template(template) {
+        return template.to_string();
+    }
+    let taken = match taken_at(path, date_patterns) {
+        Some(t) => t.with_timezone(&Local),
+        None => match fs::metadata(path).and_then(|m| m.modified()) {
+            Ok(modified) => DateTime::<Local>::from(modified),
+            Err(_) => Local::now(),
+        },
+    };
+    let mut name = template.to_string();
+    for (placeholder, format) in PLACEHOLDERS {
+        name = name.replace(placeholder, &taken.format(format).to_string());
+    }
+    name
+}
+
+/// The album for a file in a subfolder of one of the synced folders: the name of the
+/// folder directly below the root, so `Trips/Iceland/day1/a.jpg` goes to "Iceland"
+/// when `Trips` is synced. `None` for files right in a root.
+pub fn subfolder(path: &Path, roots: &[SourceFolder]) -> Option<String> {
+    let relative = roots.iter().find_map(|root| path.strip_prefix(&root.path).ok())?;
+    let mut components = relative.components();
+    let first = components.next()?;
+    components.next()?;
+    Some(first.as_os_str().to_string_lossy().to_string())
+}
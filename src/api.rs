@@ -0,0 +1,758 @@
+use crate::compat;
+use crate::config::{AlbumRole, FormFields, Visibility};
+use crate::error::{SyncError, classify};
+use crate::connections;
+use crate::config::SourceFolder;
+use crate::handler::{Contents, Payload, handler_for};
+use crate::history::{hash_bytes, hash_file, relative_path};
+use crate::rate_budget;
+use crate::run;
+use crate::session;
+use crate::shared_link;
+use crate::status::Status;
+use crate::transform::{Downscale, HeicToJpeg};
+use anyhow::{Result, bail};
+use chrono::{DateTime, Utc};
+use indicatif::HumanBytes;
+use log::{debug, info, warn};
+use reqwest::{Body, Client, Method, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use futures_util::StreamExt;
+use tokio::io::AsyncRead;
+use tokio_util::io::ReaderStream;
+use tracing::{Instrument, Span, info_span, instrument};
+
+pub const DEVICE_ID: &str = "rust-uploader-v1";
+/// Returned by uploads that are done but left no asset to link (the server rejected a
+/// duplicate without saying which asset it was, or it was deliberately skipped)
+pub const DUPLICATE_UNKNOWN_ID: &str = "DUPLICATE_UNKNOWN_ID";
+const UPLOAD_CHUNK_SIZE: usize = 256 * 1024;
+
+/// Adds what every server request carries: the API key (or login session) and the current run ID.
+pub trait Authed {
+    fn authed(self, key: &str) -> Self;
+}
+
+/// Sends a request; transport failures and error statuses come back as `SyncError`s.
+pub trait Checked {
+    fn checked(self) -> impl Future<Output = std::result::Result<Response, SyncError>> + Send;
+}
+
+impl Checked for RequestBuilder {
+    async fn checked(self) -> std::result::Result<Response, SyncError> {
+        let resp = self.send().await.map_err(|e| SyncError::network(e, None))?;
+        if !resp.status().is_success() {
+            return Err(SyncError::from_response(resp, None).await);
+        }
+        Ok(resp)
+    }
+}
+
+impl Authed for RequestBuilder {
+    fn authed(self, key: &str) -> Self {
+        // Every request to the server goes through here, so the budget sees them all
+        rate_budget::charge(key);
+        let request = if session::is_session_token(key) {
+            self.bearer_auth(key)
+        } else if shared_link::is_share_key(key) {
+            self.header(shared_link::SHARE_KEY_HEADER, key)
+        } else {
+            self.header("x-api-key", key)
+        };
+        match run::current() {
+            id if id.is_empty() => request,
+            id => request.header(run::RUN_ID_HEADER, id),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Album {
+    id: String,
+    #[serde(rename = "albumName")]
+    album_name: String,
+}
+
+/// `GET /api/albums` returns a plain array; a paged form (`items` plus `nextPage`)
+/// is followed too. Entries are kept as raw JSON so one odd album can't fail the lookup.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AlbumListing {
+    All(Vec<serde_json::Value>),
+    Page {
+        items: Vec<serde_json::Value>,
+        #[serde(rename = "nextPage")]
+        next_page: Option<serde_json::Value>,
+    },
+}
+
+#[derive(Deserialize)]
+struct AssetResponse {
+    id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlbumInfo {
+    pub updated_at: String,
+    #[serde(default)]
+    pub assets: Vec<AlbumAsset>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetInfo {
+    #[serde(default)]
+    pub owner_id: String,
+    #[serde(default)]
+    pub device_id: String,
+    /// Set once the server has generated the thumbnail
+    pub thumbhash: Option<String>,
+    #[serde(default)]
+    pub is_trashed: bool,
+}
+
+#[derive(Deserialize)]
+struct UserResponse {
+    id: String,
+    #[serde(default)]
+    email: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlbumAsset {
+    pub id: String,
+    #[serde(default)]
+    pub original_file_name: String,
+}
+
+pub async fn get_active_url(client: &Client, local: &str, external: &str) -> Option<String> {
+    if !local.is_empty() {
+        info!("Checking connection to: {}...", local);
+        // Updated API endpoint
+        if client.get(format!("{}/api/server/ping", local)).timeout(Duration::from_secs(2)).send().await.is_ok() {
+            info!("Local network detected.");
+            compat::detect(client, local).await;
+            return Some(local.to_string());
+        }
+    }
+    
+    if !external.is_empty() {
+        info!("Switching to External URL.");
+        compat::detect(client, external).await;
+        return Some(external.to_string());
+    }
+    None
+}
+
+/// Whether a request failed because the server doesn't have (or show us) the resource.
+pub fn is_not_found(e: &anyhow::Error) -> bool {
+    classify(e)
+        .and_then(SyncError::status)
+        .is_some_and(|s| s == StatusCode::NOT_FOUND || s == StatusCode::BAD_REQUEST)
+}
+
+pub async fn get_album_id(client: &Client, base_url: &str, key: &str, name: &str) -> Result<Option<String>> {
+    let mut url = compat::url(base_url, "/api/albums");
+    loop {
+        let resp = client.get(&url).authed(key).checked().await?;
+
+        let (albums, next_page) = match resp.json::<AlbumListing>().await? {
+            AlbumListing::All(albums) => (albums, None),
+            AlbumListing::Page { items, next_page } => (items, next_page),
+        };
+        for entry in albums {
+            match serde_json::from_value::<Album>(entry) {
+                Ok(album) if album.album_name == name => return Ok(Some(album.id)),
+                Ok(_) => {}
+                Err(e) => debug!("Ignoring unreadable album entry: {}", e),
+            }
+        }
+        let page = match next_page {
+            Some(serde_json::Value::String(page)) => page,
+            Some(serde_json::Value::Number(page)) => page.to_string(),
+            _ => return Ok(None),
+        };
+        url = compat::url(base_url, &format!("/api/albums?page={}", page));
+    }
+}
+
+/// Creates an empty album and returns its ID.
+pub async fn create_album(client: &Client, base_url: &str, key: &str, name: &str) -> Result<String> {
+    let url = compat::url(base_url, "/api/albums");
+    let body = serde_json::json!({ "albumName": name });
+    let resp = client.post(&url).authed(key).json(&body).checked().await?;
+    let album: Album = resp.json().await?;
+    Ok(album.id)
+}
+
+/// Shares an album with users, given as (user ID, role) pairs.
+pub async fn share_album(client: &Client, base_url: &str, key: &str, album_id: &str, users: &[(String, AlbumRole)]) -> Result<()> {
+    let url = compat::url(base_url, &format!("/api/albums/{}/users", album_id));
+    let album_users: Vec<_> = users
+        .iter()
+        .map(|(id, role)| serde_json::json!({ "userId": id, "role": role.as_str() }))
+        .collect();
+    let body = serde_json::json!({ "albumUsers": album_users });
+    client.put(&url).authed(key).json(&body).checked().await?;
+    Ok(())
+}
+
+/// Fetches an album's details; `with_assets = false` skips the (possibly huge) asset list.
+pub async fn get_album_info(client: &Client, base_url: &str, key: &str, album_id: &str, with_assets: bool) -> Result<AlbumInfo> {
+    let url = compat::url(base_url, &format!("/api/albums/{}?withoutAssets={}", album_id, !with_assets));
+    let resp = client.get(&url).authed(key).checked().await?;
+    Ok(resp.json().await?)
+}
+
+/// Returns the ID of the user the API key belongs to.
+pub async fn get_my_user_id(client: &Client, base_url: &str, key: &str) -> Result<String> {
+    let url = compat::url(base_url, "/api/users/me");
+    let resp = client.get(&url).authed(key).checked().await?;
+    Ok(resp.json::<UserResponse>().await?.id)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserQuota {
+    /// `None` for users without a quota
+    #[serde(default)]
+    pub quota_size_in_bytes: Option<u64>,
+    #[serde(default)]
+    pub quota_usage_in_bytes: u64,
+}
+
+/// The storage quota of the user the API key belongs to.
+pub async fn get_my_quota(client: &Client, base_url: &str, key: &str) -> Result<UserQuota> {
+    let url = compat::url(base_url, "/api/users/me");
+    let resp = client.get(&url).authed(key).checked().await?;
+    Ok(resp.json().await?)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerStorage {
+    pub disk_size_raw: u64,
+    pub disk_use_raw: u64,
+}
+
+/// How full the disk holding the server's library is.
+pub async fn get_server_storage(client: &Client, base_url: &str, key: &str) -> Result<ServerStorage> {
+    let url = compat::url(base_url, "/api/server/storage");
+    let resp = client.get(&url).authed(key).checked().await?;
+    Ok(resp.json().await?)
+}
+
+/// Fails with a clear message when the server refuses the API key, so a bad key
+/// isn't discovered file by file as upload errors.
+pub async fn validate_key(client: &Client, base_url: &str, key: &str) -> Result<()> {
+    let resp = client.get(compat::url(base_url, "/api/users/me")).authed(key).send().await?;
+    if resp.status() == StatusCode::UNAUTHORIZED && session::is_session_token(key) {
+        bail!("The login session for {} has ended; run `login` again", base_url);
+    }
+    if resp.status() == StatusCode::UNAUTHORIZED || resp.status() == StatusCode::FORBIDDEN {
+        bail!("Invalid or expired API key for {} (server said {})", base_url, resp.status());
+    }
+    if !resp.status().is_success() {
+        return Err(SyncError::from_response(resp, None).await.into());
+    }
+    Ok(())
+}
+
+/// Finds a user by email (case-insensitive) among the server's users.
+pub async fn find_user_id(client: &Client, base_url: &str, key: &str, email: &str) -> Result<Option<String>> {
+    let url = compat::url(base_url, "/api/users");
+    let resp = client.get(&url).authed(key).checked().await?;
+    let users: Vec<UserResponse> = resp.json().await?;
+    Ok(users.into_iter().find(|u| u.email.eq_ignore_ascii_case(email)).map(|u| u.id))
+}
+
+/// Looks up a single asset; `None` if the server doesn't know it.
+pub async fn get_asset(client: &Client, base_url: &str, key: &str, asset_id: &str) -> Result<Option<AssetInfo>> {
+    let url = compat::url(base_url, &format!("/api/assets/{}", asset_id));
+    let resp = client.get(&url).authed(key).send().await?;
+    if resp.status() == StatusCode::NOT_FOUND || resp.status() == StatusCode::BAD_REQUEST {
+        return Ok(None);
+    }
+    if !resp.status().is_success() {
+        return Err(SyncError::from_response(resp, None).await.into());
+    }
+    Ok(Some(resp.json().await?))
+}
+
+#[instrument(skip_all, fields(album_id = album_id, assets = asset_ids.len()))]
+pub async fn add_to_album(client: &Client, base_url: &str, key: &str, album_id: &str, asset_ids: &[String]) -> Result<()> {
+    let url = compat::url(base_url, &format!("/api/albums/{}/assets", album_id));
+    let body = serde_json::json!({ "ids": asset_ids });
+    
+    client.put(&url)
+        .authed(key)
+        .json(&body)
+        .checked()
+        .await?;
+        
+    info!("   -- Added {} assets to album", asset_ids.len());
+    Ok(())
+}
+
+/// Takes assets out of an album; the assets themselves stay on the server.
+pub async fn remove_from_album(client: &Client, base_url: &str, key: &str, album_id: &str, asset_ids: &[String]) -> Result<()> {
+    let url = compat::url(base_url, &format!("/api/albums/{}/assets", album_id));
+    let body = serde_json::json!({ "ids": asset_ids });
+    client.delete(&url).authed(key).json(&body).checked().await?;
+    Ok(())
+}
+
+/// Asset fields to change after upload; only those set are sent.
+#[derive(Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_favorite: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date_time_original: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+}
+
+impl AssetUpdate {
+    /// Names of the fields that are set, for logging; empty when there's nothing to send.
+    pub fn fields(&self) -> Vec<&'static str> {
+        [
+            ("description", self.description.is_some()),
+            ("favorite", self.is_favorite.is_some()),
+            ("capture date", self.date_time_original.is_some()),
+            ("location", self.latitude.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(name, set)| set.then_some(name))
+        .collect()
+    }
+}
+
+pub async fn update_asset(client: &Client, base_url: &str, key: &str, asset_id: &str, changes: &AssetUpdate) -> Result<()> {
+    let url = compat::url(base_url, &format!("/api/assets/{}", asset_id));
+    client.put(&url).authed(key).json(changes).checked().await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct UploadCheck {
+    results: Vec<UploadCheckResult>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UploadCheckResult {
+    #[serde(default)]
+    id: String,
+    action: String,
+    asset_id: Option<String>,
+    #[serde(default)]
+    is_trashed: bool,
+}
+
+/// An asset the server already has with the same content.
+pub struct ChecksumMatch {
+    pub asset_id: String,
+    pub is_trashed: bool,
+}
+
+/// Asks the server whether it already has a file with this SHA-1 (from any device).
+#[instrument(name = "checksum_lookup", skip_all, fields(file = name))]
+pub async fn find_by_checksum(client: &Client, base_url: &str, key: &str, name: &str, sha1: &str) -> Result<Option<ChecksumMatch>> {
+    Ok(find_all_by_checksum(client, base_url, key, &[(name, sha1)]).await?.remove(name))
+}
+
+/// `find_by_checksum` for many (name, SHA-1) pairs in one request; matches are keyed by name.
+pub async fn find_all_by_checksum(client: &Client, base_url: &str, key: &str, files: &[(&str, &str)]) -> Result<HashMap<String, ChecksumMatch>> {
+    let url = compat::url(base_url, "/api/assets/bulk-upload-check");
+    let assets: Vec<_> = files.iter().map(|(name, sha1)| serde_json::json!({ "id": name, "checksum": sha1 })).collect();
+    let body = serde_json::json!({ "assets": assets });
+    let resp = client.post(&url).authed(key).json(&body).checked().await?;
+    let check: UploadCheck = resp.json().await?;
+    Ok(check
+        .results
+        .into_iter()
+        .filter(|r| r.action == "reject")
+        .filter_map(|r| Some((r.id, ChecksumMatch { asset_id: r.asset_id?, is_trashed: r.is_trashed })))
+        .collect())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashedAsset {
+    pub id: String,
+    #[serde(default)]
+    pub original_file_name: String,
+    #[serde(default)]
+    pub device_id: String,
+    #[serde(default)]
+    pub is_trashed: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SearchPage {
+    items: Vec<TrashedAsset>,
+    next_page: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SearchResponse {
+    assets: SearchPage,
+}
+
+/// Everything in the user's trash, oldest pages first.
+pub async fn list_trashed(client: &Client, base_url: &str, key: &str) -> Result<Vec<TrashedAsset>> {
+    let url = compat::url(base_url, "/api/search/metadata");
+    let mut trashed = Vec::new();
+    let mut page = Some("1".to_string());
+    while let Some(current) = page {
+        // Only trashed assets carry a deletion date, so any `trashedAfter` picks them out
+        let body = serde_json::json!({
+            "withDeleted": true,
+            "trashedAfter": "1970-01-01T00:00:00.000Z",
+            "page": current.parse::<u32>().unwrap_or(1),
+            "size": 1000,
+        });
+        let resp = client.post(&url).authed(key).json(&body).checked().await?;
+        let found: SearchResponse = resp.json().await?;
+        trashed.extend(found.assets.items.into_iter().filter(|a| a.is_trashed));
+        page = found.assets.next_page;
+    }
+    Ok(trashed)
+}
+
+/// IDs of the (untrashed) assets with this original filename.
+pub async fn find_by_file_name(client: &Client, base_url: &str, key: &str, name: &str) -> Result<Vec<String>> {
+    let body = serde_json::json!({ "originalFileName": name, "size": 100 });
+    let resp = client.post(compat::url(base_url, "/api/search/metadata")).authed(key).json(&body).checked().await?;
+    let found: SearchResponse = resp.json().await?;
+    Ok(found
+        .assets
+        .items
+        .into_iter()
+        .filter(|a| !a.is_trashed && a.original_file_name == name)
+        .map(|a| a.id)
+        .collect())
+}
+
+/// Restores every asset in the user's trash.
+pub async fn restore_all_trash(client: &Client, base_url: &str, key: &str) -> Result<()> {
+    client.post(compat::url(base_url, "/api/trash/restore")).authed(key).checked().await?;
+    Ok(())
+}
+
+/// Permanently deletes everything in the user's trash.
+pub async fn empty_trash(client: &Client, base_url: &str, key: &str) -> Result<()> {
+    client.post(compat::url(base_url, "/api/trash/empty")).authed(key).checked().await?;
+    Ok(())
+}
+
+/// Deletes assets for good, skipping the trash.
+pub async fn delete_permanently(client: &Client, base_url: &str, key: &str, asset_ids: &[String]) -> Result<()> {
+    let body = serde_json::json!({ "ids": asset_ids, "force": true });
+    client.delete(compat::url(base_url, "/api/assets")).authed(key).json(&body).checked().await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Library {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub import_paths: Vec<String>,
+}
+
+/// External libraries; listing them (like creating them) needs an admin API key.
+pub async fn list_libraries(client: &Client, base_url: &str, key: &str) -> Result<Vec<Library>> {
+    let resp = client.get(compat::url(base_url, "/api/libraries")).authed(key).checked().await?;
+    Ok(resp.json().await?)
+}
+
+pub async fn create_library(client: &Client, base_url: &str, key: &str, owner_id: &str, name: &str, import_paths: &[String]) -> Result<Library> {
+    let body = serde_json::json!({ "ownerId": owner_id, "name": name, "importPaths": import_paths });
+    let resp = client.post(compat::url(base_url, "/api/libraries")).authed(key).json(&body).checked().await?;
+    Ok(resp.json().await?)
+}
+
+pub async fn set_library_paths(client: &Client, base_url: &str, key: &str, library_id: &str, import_paths: &[String]) -> Result<()> {
+    let url = compat::url(base_url, &format!("/api/libraries/{}", library_id));
+    let body = serde_json::json!({ "importPaths": import_paths });
+    client.put(&url).authed(key).json(&body).checked().await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportPathCheck {
+    pub import_path: String,
+    pub is_valid: bool,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// Asks the server whether it can read each import path.
+pub async fn validate_library_paths(client: &Client, base_url: &str, key: &str, library_id: &str, import_paths: &[String]) -> Result<Vec<ImportPathCheck>> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Validation {
+        #[serde(default)]
+        import_paths: Vec<ImportPathCheck>,
+    }
+    let url = compat::url(base_url, &format!("/api/libraries/{}/validate", library_id));
+    let body = serde_json::json!({ "importPaths": import_paths });
+    let resp = client.post(&url).authed(key).json(&body).checked().await?;
+    Ok(resp.json::<Validation>().await?.import_paths)
+}
+
+/// Queues a scan for new, changed and removed files; the server works through it on its own.
+pub async fn scan_library(client: &Client, base_url: &str, key: &str, library_id: &str) -> Result<()> {
+    let url = compat::url(base_url, &format!("/api/libraries/{}/scan", library_id));
+    client.post(&url).authed(key).json(&serde_json::json!({})).checked().await?;
+    Ok(())
+}
+
+pub async fn restore_from_trash(client: &Client, base_url: &str, key: &str, asset_ids: &[String]) -> Result<()> {
+    let url = compat::url(base_url, "/api/trash/restore/assets");
+    let body = serde_json::json!({ "ids": asset_ids });
+    client.post(&url).authed(key).json(&body).checked().await?;
+    Ok(())
+}
+
+/// Groups assets into a stack; the first ID becomes the stack's primary asset.
+pub async fn create_stack(client: &Client, base_url: &str, key: &str, asset_ids: &[String]) -> Result<()> {
+    let url = compat::url(base_url, "/api/stacks");
+    let body = serde_json::json!({ "assetIds": asset_ids });
+    client.post(&url).authed(key).json(&body).checked().await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct TagResponse {
+    id: String,
+}
+
+/// Creates the tag if needed (`parent/child` names nest) and returns its ID.
+pub async fn upsert_tag(client: &Client, base_url: &str, key: &str, name: &str) -> Result<String> {
+    let url = compat::url(base_url, "/api/tags");
+    let body = serde_json::json!({ "tags": [name] });
+    let resp = client.put(&url).authed(key).json(&body).checked().await?;
+    let tags: Vec<TagResponse> = resp.json().await?;
+    match tags.into_iter().next() {
+        Some(tag) => Ok(tag.id),
+        None => bail!("Server returned no tag for '{}'", name),
+    }
+}
+
+pub async fn tag_assets(client: &Client, base_url: &str, key: &str, tag_id: &str, asset_ids: &[String]) -> Result<()> {
+    let url = compat::url(base_url, &format!("/api/tags/{}/assets", tag_id));
+    let body = serde_json::json!({ "ids": asset_ids });
+    client.put(&url).authed(key).json(&body).checked().await?;
+    Ok(())
+}
+
+/// Everything an upload task needs to talk to the server; shared between tasks via `Arc`.
+pub struct Uploader {
+    pub client: Client,
+    pub base_url: String,
+    pub key: String,
+    pub fields: FormFields,
+    pub status: Arc<Status>,
+    pub date_patterns: Vec<String>,
+    pub downscale: Option<Downscale>,
+    pub heic_to_jpeg: Option<HeicToJpeg>,
+    pub strip_gps: bool,
+    pub upload_timeout: Option<Duration>,
+    pub stall_timeout: Option<Duration>,
+    /// Status is keyed by the path under these, as two folders can hold the same name
+    pub folders: Vec<SourceFolder>,
+}
+
+// reqwest has no "no timeout" per request; this stands in for one
+const NO_UPLOAD_LIMIT: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// Resolves once `last_progress` is more than `limit` ago: the body has stopped moving.
+/// Never once it's `None`, the body being sent: a big video can keep the server busy
+/// for minutes before it answers.
+async fn stalled(last_progress: &Mutex<Option<Instant>>, limit: Duration) {
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        if last_progress.lock().unwrap().is_some_and(|at| at.elapsed() > limit) {
+            return;
+        }
+    }
+}
+
+/// Per-asset extras sent along with the file.
+#[derive(Default)]
+pub struct AssetMeta {
+    /// ID of the already uploaded video half of a Live Photo
+    pub live_photo_video_id: Option<String>,
+    pub favorite: bool,
+    pub visibility: Option<Visibility>,
+    /// Asset whose file this upload replaces, keeping its ID, albums and tags
+    pub replaces: Option<String>,
+}
+
+impl Uploader {
+    /// Uploads `path`, letting its `Handler` decide what exactly is sent. With
+    /// `meta.replaces` it becomes the new original of that asset instead.
+    #[instrument(name = "upload", skip_all, fields(file = %path.display(), bytes, status))]
+    pub async fn upload_asset(&self, path: &Path, meta: &AssetMeta) -> Result<String> {
+        let Self { client, base_url, key, fields, status, date_patterns, .. } = self;
+        let handler = handler_for(path);
+        let filename = path.file_name().unwrap().to_string_lossy();
+        debug!("{} is handled as a {}", filename, handler.kind());
+        let metadata = fs::metadata(path).map_err(|e| SyncError::io(path, e))?;
+        handler.validate(path, metadata.len())?;
+
+        // Create timestamps in strict ISO format for Immich. Filesystem dates change
+        // when files are copied, so the EXIF capture date (or one in the filename) wins.
+        let modified: DateTime<Utc> = metadata.modified().unwrap_or(SystemTime::now()).into();
+        let created = match handler.taken_at(path, date_patterns) {
+            Some(taken) => taken.to_rfc3339(),
+            None => DateTime::<Utc>::from(metadata.created().unwrap_or(SystemTime::now())).to_rfc3339(),
+        };
+
+        let device_asset_id = format!("{}-{}-{}", filename, metadata.len(), modified.timestamp());
+
+        // Prepare multipart form, streamed in chunks so progress can be reported
+        let Payload { contents, name: upload_name, mime } = handler.prepare(path, self).instrument(info_span!("prepare")).await?;
+        let (total, reader): (u64, Box<dyn AsyncRead + Send + Unpin>) = match &contents {
+            Contents::File(file) => {
+                let opened = tokio::fs::File::open(file).await.map_err(|e| SyncError::io(file, e))?;
+                (metadata.len(), Box::new(opened))
+            }
+            Contents::Bytes(bytes) => (bytes.len() as u64, Box::new(std::io::Cursor::new(bytes.clone()))),
+        };
+        Span::current().record("bytes", total);
+        let key_in_status = relative_path(path, &self.folders);
+        status.start_upload(&key_in_status, total);
+
+        let (progress, name) = (status.clone(), key_in_status.clone());
+        let last_progress = Arc::new(Mutex::new(Some(Instant::now())));
+        let touched = last_progress.clone();
+        let mut sent = 0;
+        let body = Body::wrap_stream(ReaderStream::with_capacity(reader, UPLOAD_CHUNK_SIZE).inspect(move |chunk| {
+            if let Ok(chunk) = chunk {
+                progress.advance(&name, chunk.len() as u64);
+                sent += chunk.len() as u64;
+                // From the last byte on it's the server's turn, which isn't a stall
+                *touched.lock().unwrap() = (sent < total).then(Instant::now);
+            }
+        }));
+
+        let part = reqwest::multipart::Part::stream_with_length(body, total)
+            .file_name(upload_name)
+            .mime_str(mime.as_ref())?;
+
+        let mut form = reqwest::multipart::Form::new()
+            .part(fields.name("assetData"), part)
+            .text(fields.name("deviceAssetId"), device_asset_id)
+            .text(fields.name("deviceId"), DEVICE_ID)
+            .text(fields.name("fileCreatedAt"), created)
+            .text(fields.name("fileModifiedAt"), modified.to_rfc3339());
+        // The replace endpoint takes only the file and its dates
+        if meta.replaces.is_none() {
+            form = form.text(fields.name("isFavorite"), meta.favorite.to_string());
+        }
+        if let Some(sidecar) = handler.sidecar(path, self) {
+            let sidecar_bytes = tokio::fs::read(&sidecar).await.map_err(|e| SyncError::io(&sidecar, e))?;
+            let sidecar_part = reqwest::multipart::Part::bytes(sidecar_bytes)
+                .file_name(sidecar.file_name().unwrap().to_string_lossy().to_string())
+                .mime_str("application/xml")?;
+            form = form.part(fields.name("sidecarData"), sidecar_part);
+        }
+        if let Some(visibility) = meta.visibility.filter(|_| meta.replaces.is_none()) {
+            form = form.text(fields.name("visibility"), visibility.as_str());
+        }
+        if let Some(video_id) = &meta.live_photo_video_id {
+            form = form.text(fields.name("livePhotoVideoId"), video_id.clone());
+        }
+        for (name, value) in fields.extra() {
+            form = form.text(name.clone(), value.clone());
+        }
+
+        let slot = connections::acquire(base_url).instrument(info_span!("wait_for_connection")).await;
+        let started = Instant::now();
+        let request = match &meta.replaces {
+            Some(asset_id) => client.put(compat::url(base_url, &format!("/api/assets/{}/original", asset_id))),
+            None => client.post(compat::url_for(&Method::POST, base_url, "/api/assets")),
+        };
+        let request = request
+            .authed(key)
+            .timeout(self.upload_timeout.unwrap_or(NO_UPLOAD_LIMIT))
+            .multipart(form)
+            .send()
+            // Until the response headers: the body going out, then the server's processing
+            .instrument(info_span!("send"));
+        let result = match self.stall_timeout {
+            Some(limit) => tokio::select! {
+                result = request => result.map_err(|e| SyncError::network(e, Some(path))),
+                _ = stalled(&last_progress, limit) => Err(SyncError::Network {
+                    path: Some(path.to_path_buf()),
+                    message: format!("Upload stalled: no progress for {}s", limit.as_secs()),
+                }),
+            },
+            None => request.await.map_err(|e| SyncError::network(e, Some(path))),
+        };
+        drop(slot);
+        status.finish_upload(&key_in_status);
+        let resp = result?;
+
+        let status_code = resp.status();
+        Span::current().record("status", status_code.as_u16());
+
+        let (action, took) = (if meta.replaces.is_some() { "replace" } else { "upload" }, started.elapsed());
+        let duration_ms = took.as_millis() as u64;
+        if status_code == StatusCode::CREATED || (meta.replaces.is_some() && status_code == StatusCode::OK) {
+            let json: AssetResponse = resp.json().await?;
+            info!(
+                file = &*filename, action, status = "ok", bytes = total, duration_ms;
+                "   -- Sent {} ({} in {:.1}s)", filename, HumanBytes(total), took.as_secs_f64()
+            );
+            Ok(json.id)
+        } else if status_code == StatusCode::OK {
+            warn!(file = &*filename, action, status = "duplicate", bytes = total, duration_ms; "File exists (Deduplicated): {}", filename);
+            let json: AssetResponse = resp.json().await?;
+            Ok(json.id)
+        } else if status_code == StatusCode::CONFLICT {
+            warn!(file = &*filename, action, status = "duplicate", bytes = total, duration_ms; "Duplicate rejected: {}", filename);
+            // Try to parse ID from error body if possible, otherwise find the asset by
+            // the checksum of what was sent
+            if let Ok(json) = resp.json::<AssetResponse>().await {
+                return Ok(json.id);
+            }
+            let checksum = match &contents {
+                Contents::File(file) => hash_file(file),
+                Contents::Bytes(bytes) => Ok(hash_bytes(bytes)),
+            };
+            match async { find_by_checksum(client, base_url, key, &filename, &checksum?).await }.await {
+                Ok(Some(existing)) if !existing.is_trashed => {
+                    info!("   -- {} is asset {} on the server", filename, existing.asset_id);
+                    Ok(existing.asset_id)
+                }
+                Ok(_) => Ok(DUPLICATE_UNKNOWN_ID.to_string()),
+                Err(e) => {
+                    warn!("Could not look up the existing asset for {}: {:?}", filename, e);
+                    Ok(DUPLICATE_UNKNOWN_ID.to_string())
+                }
+            }
+        } else {
+            Err(SyncError::from_response(resp, Some(path)).await.into())
+        }
+    }
+}
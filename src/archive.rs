@@ -0,0 +1,110 @@
+use crate::metadata::taken_at;
+use crate::state;
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Local, Utc};
+use serde::Serialize;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+const MANIFEST: &str = "manifest.jsonl";
+// Remote archives can't be appended to; the manifest is kept here and copied up.
+const LOCAL_REMOTE_MANIFEST: &str = "immich_archive_manifest.jsonl";
+
+/// Where the second copy of every uploaded file goes.
+#[derive(Clone)]
+pub enum ArchiveTarget {
+    /// A directory, e.g. on another disk
+    Dir(PathBuf),
+    /// An rclone destination such as `b2:photos-archive`
+    Rclone(String),
+}
+
+#[derive(Serialize)]
+struct ManifestEntry<'a> {
+    name: &'a str,
+    sha1: &'a str,
+    asset_id: &'a str,
+    path: &'a str,
+    archived_at: DateTime<Utc>,
+}
+
+/// Copies `file` into `YYYY/MM/` (by capture date, else modification time) of the
+/// archive and appends a manifest line. Only called for files the history hasn't seen,
+/// so the archive is deduplicated the same way uploads are.
+pub async fn store(target: &ArchiveTarget, file: &Path, sha1: &str, asset_id: &str, date_patterns: &[String]) -> Result<()> {
+    let name = file.file_name().unwrap().to_string_lossy().to_string();
+    let taken = match taken_at(file, date_patterns) {
+        Some(t) => t.with_timezone(&Local),
+        None => DateTime::<Local>::from(fs::metadata(file)?.modified()?),
+    };
+    let folder = taken.format("%Y/%m").to_string();
+
+    let relative = match target {
+        ArchiveTarget::Dir(root) => {
+            let dir = root.join(&folder);
+            fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+            let Some(dest) = free_name(&dir, &name, sha1) else {
+                return Ok(());
+            };
+            tokio::fs::copy(file, dir.join(&dest)).await.with_context(|| format!("Failed to archive {}", name))?;
+            format!("{}/{}", folder, dest)
+        }
+        ArchiveTarget::Rclone(remote) => {
+            let relative = format!("{}/{}", folder, name);
+            rclone(&[
+                "copyto".as_ref(),
+                "--ignore-existing".as_ref(),
+                file.as_os_str(),
+                format!("{}/{}", remote.trim_end_matches('/'), relative).as_ref(),
+            ])
+            .await?;
+            relative
+        }
+    };
+
+    let entry = ManifestEntry { name: &name, sha1, asset_id, path: &relative, archived_at: Utc::now() };
+    let manifest = match target {
+        ArchiveTarget::Dir(root) => root.join(MANIFEST),
+        ArchiveTarget::Rclone(_) => state::root().join(LOCAL_REMOTE_MANIFEST),
+    };
+    let mut out = OpenOptions::new().create(true).append(true).open(&manifest)?;
+    writeln!(out, "{}", serde_json::to_string(&entry)?)?;
+    Ok(())
+}
+
+/// Uploads the locally kept manifest of an rclone archive; a no-op for directories.
+pub async fn sync_manifest(target: &ArchiveTarget) -> Result<()> {
+    let local = state::root().join(LOCAL_REMOTE_MANIFEST);
+    if let ArchiveTarget::Rclone(remote) = target
+        && local.exists()
+    {
+        let dest = format!("{}/{}", remote.trim_end_matches('/'), MANIFEST);
+        rclone(&["copyto".as_ref(), local.as_os_str(), dest.as_ref()]).await?;
+    }
+    Ok(())
+}
+
+/// `name` if that's free in `dir`, else `stem-<hash>.ext`; `None` when that copy exists
+/// already (same content archived under the same name before).
+fn free_name(dir: &Path, name: &str, sha1: &str) -> Option<String> {
+    if !dir.join(name).exists() {
+        return Some(name.to_string());
+    }
+    let path = Path::new(name);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let alt = match path.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, &sha1[..8], ext.to_string_lossy()),
+        None => format!("{}-{}", stem, &sha1[..8]),
+    };
+    (!dir.join(&alt).exists()).then_some(alt)
+}
+
+async fn rclone(args: &[&std::ffi::OsStr]) -> Result<()> {
+    let output = Command::new("rclone").args(args).output().await.context("Failed to run rclone")?;
+    if !output.status.success() {
+        bail!("rclone failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
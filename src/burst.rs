@@ -0,0 +1,69 @@
+use crate::metadata::taken_at;
+use chrono::{DateTime, Duration, FixedOffset};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Files that could belong to one burst: same folder, name prefix and extension.
+type SeriesKey = (PathBuf, String, String);
+/// Frame number, path and capture time.
+type Frame<'a> = (u64, &'a Path, Option<DateTime<FixedOffset>>);
+
+/// Groups files into burst sequences: consecutively numbered names with the same prefix
+/// (`IMG_0041.JPG`, `IMG_0042.JPG`, ...) whose capture times are at most `window` apart
+/// from one frame to the next. Only sequences of `min_frames` or more are returned,
+/// each in shooting order.
+pub fn find_bursts(files: &[&Path], window: Duration, min_frames: usize, date_patterns: &[String]) -> Vec<Vec<PathBuf>> {
+    let mut series: BTreeMap<SeriesKey, Vec<(u64, &Path)>> = BTreeMap::new();
+    for &path in files {
+        if let Some((prefix, number)) = split_number(path) {
+            let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+            let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+            series.entry((dir, prefix, ext)).or_default().push((number, path));
+        }
+    }
+
+    let mut bursts = Vec::new();
+    for mut frames in series.into_values() {
+        frames.sort_by_key(|f| f.0);
+        let mut current: Vec<Frame> = Vec::new();
+        for (number, path) in frames {
+            let time = shot_at(path, date_patterns);
+            let continues = current.last().is_some_and(|&(prev, _, prev_time)| {
+                number == prev + 1 && matches!((prev_time, time), (Some(a), Some(b)) if (b - a).abs() <= window)
+            });
+            if !continues {
+                flush(&mut current, min_frames, &mut bursts);
+            }
+            current.push((number, path, time));
+        }
+        flush(&mut current, min_frames, &mut bursts);
+    }
+    bursts
+}
+
+fn flush(current: &mut Vec<Frame>, min_frames: usize, bursts: &mut Vec<Vec<PathBuf>>) {
+    if current.len() >= min_frames.max(2) {
+        bursts.push(current.iter().map(|f| f.1.to_path_buf()).collect());
+    }
+    current.clear();
+}
+
+/// `IMG_0042.JPG` -> (`IMG_`, 42): the trailing number of the file stem.
+fn split_number(path: &Path) -> Option<(String, u64)> {
+    let stem = path.file_stem()?.to_string_lossy();
+    let digits = stem.len() - stem.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    if digits == 0 {
+        return None;
+    }
+    let (prefix, number) = stem.split_at(stem.len() - digits);
+    Some((prefix.to_string(), number.parse().ok()?))
+}
+
+// EXIF/filename time where available; bursts shot within one second need the
+// modification time's sub-second precision otherwise.
+fn shot_at(path: &Path, date_patterns: &[String]) -> Option<DateTime<FixedOffset>> {
+    taken_at(path, date_patterns).or_else(|| {
+        let modified = path.metadata().and_then(|m| m.modified()).ok()?;
+        Some(DateTime::<chrono::Utc>::from(modified).fixed_offset())
+    })
+}
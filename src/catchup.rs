@@ -0,0 +1,80 @@
+use crate::api::{find_by_checksum, get_active_url};
+use crate::config::Config;
+use crate::health::Health;
+use crate::history::History;
+use anyhow::{Result, bail};
+use chrono::Utc;
+use log::{info, warn};
+use reqwest::Client;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::Notify;
+
+// How often the clock watcher compares wall-clock time with monotonic time.
+const CLOCK_CHECK: Duration = Duration::from_secs(60);
+
+/// How long ago the last successful pass was, if longer than `threshold`. Covers
+/// restarts after a long shutdown and recovering from a long outage.
+pub fn overdue(threshold: Duration) -> Option<Duration> {
+    let last = Health::load().last_success?;
+    let gap = (Utc::now() - last).to_std().unwrap_or_default();
+    (gap > threshold).then_some(gap)
+}
+
+/// Notifies the returned handle after the machine wakes from a sleep longer than
+/// `threshold`. The monotonic clock stands still during suspend while wall-clock
+/// time keeps going, so a sleep shows up as the difference between the two.
+pub fn spawn_clock_watcher(threshold: Duration) -> Arc<Notify> {
+    let woke = Arc::new(Notify::new());
+    let notify = woke.clone();
+    tokio::spawn(async move {
+        loop {
+            let (wall, mono) = (SystemTime::now(), Instant::now());
+            tokio::time::sleep(CLOCK_CHECK).await;
+            let slept = wall.elapsed().unwrap_or_default().saturating_sub(mono.elapsed());
+            if slept > threshold {
+                info!("Woke up after about {} h asleep.", slept.as_secs() / 3600);
+                notify.notify_one();
+            }
+        }
+    });
+    woke
+}
+
+/// Checks a random sample of uploaded files against the server by checksum and drops
+/// the missing ones from the history, so the following full scan uploads them again.
+pub async fn verify_sample(client: &Client, config: &Config) -> Result<()> {
+    // Converted or cleaned-up uploads don't match the local checksum
+    if config.downscale.is_some() || config.heic_to_jpeg.is_some() || config.strip_gps {
+        info!("Skipping upload verification: uploads are modified before sending.");
+        return Ok(());
+    }
+    let Some(base_url) = get_active_url(client, &config.local_url, &config.ext_url).await else {
+        bail!("Could not connect to any Immich instance.");
+    };
+    let mut history = History::open()?;
+    let order = RandomState::new();
+    let mut sample = Vec::new();
+    for job in config.jobs() {
+        sample.extend(history.hashed(&job)?.into_iter().map(|(name, hash)| (job.clone(), name, hash)));
+    }
+    sample.sort_by_key(|(_, name, _)| order.hash_one(name));
+    sample.truncate(config.catch_up_sample);
+
+    let mut missing = 0;
+    for (job, name, hash) in &sample {
+        match find_by_checksum(client, &base_url, &config.api_key, name, hash).await? {
+            Some(asset) if asset.is_trashed => warn!("{} is in the server's trash.", name),
+            Some(_) => {}
+            None => {
+                warn!("{} is no longer on the server, uploading it again.", name);
+                history.remove(job, std::slice::from_ref(hash))?;
+                missing += 1;
+            }
+        }
+    }
+    info!("Verified {} earlier upload(s), {} missing.", sample.len(), missing);
+    Ok(())
+}
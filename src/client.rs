@@ -0,0 +1,119 @@
+use crate::config::ConnectionSettings;
+use anyhow::{Context, Result, bail};
+use reqwest::{Certificate, ClientBuilder, Identity, NoProxy, Proxy};
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{ClientConfig, ServerName};
+use rustls_pemfile::Item;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Applies timeouts, keep-alive, the proxy, `IMMICH_CA_BUNDLE`, `IMMICH_CERT_FINGERPRINT`
+/// and `IMMICH_CLIENT_CERT` to a client. A pinned certificate is accepted whoever signed
+/// it (self-signed included), and nothing else is.
+pub fn configure(builder: ClientBuilder, settings: &ConnectionSettings) -> Result<ClientBuilder> {
+    let mut builder = builder.pool_idle_timeout(settings.pool_idle_timeout).tcp_keepalive(settings.tcp_keepalive);
+    // reqwest's default is no limit, which is what 0 asks for
+    if let Some(timeout) = settings.request_timeout {
+        builder = builder.timeout(timeout);
+    }
+    if let Some(timeout) = settings.connect_timeout {
+        builder = builder.connect_timeout(timeout);
+    }
+    // Without one, reqwest already follows HTTP(S)_PROXY and NO_PROXY
+    if let Some(proxy) = &settings.proxy {
+        let proxy = Proxy::all(proxy).with_context(|| format!("Invalid IMMICH_PROXY '{}'", proxy))?;
+        builder = builder.proxy(proxy.no_proxy(NoProxy::from_env()));
+    }
+    if let Some(path) = &settings.ca_bundle {
+        let pem = fs::read(path).with_context(|| format!("Failed to read CA bundle {}", path.display()))?;
+        for cert in Certificate::from_pem_bundle(&pem).with_context(|| format!("Invalid CA bundle {}", path.display()))? {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    if let Some(pin) = &settings.pinned_sha256 {
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(PinnedCert(pin.clone())));
+        let config = match &settings.client_cert {
+            Some(_) => {
+                let (certs, key) = pem_identity(settings)?;
+                config.with_client_auth_cert(certs, key).context("Invalid client certificate")?
+            }
+            None => config.with_no_client_auth(),
+        };
+        return Ok(builder.use_preconfigured_tls(config));
+    }
+    if let Some(path) = &settings.client_cert {
+        builder = builder.identity(native_identity(path, settings)?);
+    }
+    Ok(builder)
+}
+
+fn is_pkcs12(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case("p12") || e.eq_ignore_ascii_case("pfx"))
+}
+
+fn native_identity(path: &Path, settings: &ConnectionSettings) -> Result<Identity> {
+    let cert = fs::read(path).with_context(|| format!("Failed to read client certificate {}", path.display()))?;
+    if is_pkcs12(path) {
+        return Identity::from_pkcs12_der(&cert, &settings.client_cert_password)
+            .with_context(|| format!("Invalid PKCS#12 client certificate {} (wrong IMMICH_CLIENT_CERT_PASSWORD?)", path.display()));
+    }
+    let key = match &settings.client_key {
+        Some(key) => fs::read(key).with_context(|| format!("Failed to read client key {}", key.display()))?,
+        None => cert.clone(),
+    };
+    Identity::from_pkcs8_pem(&cert, &key).context(
+        "Invalid client certificate; the key must be PKCS#8 PEM (convert with `openssl pkcs8 -topk8 -nocrypt`) or use a .p12 file",
+    )
+}
+
+// With a pinned server certificate the connection is rustls's, which takes PEM only
+fn pem_identity(settings: &ConnectionSettings) -> Result<(Vec<rustls::Certificate>, rustls::PrivateKey)> {
+    let path = settings.client_cert.as_deref().unwrap();
+    if is_pkcs12(path) {
+        bail!("With IMMICH_CERT_FINGERPRINT the client certificate must be PEM, not PKCS#12");
+    }
+    let mut items = Vec::new();
+    for file in [Some(path), settings.client_key.as_deref()].into_iter().flatten() {
+        let pem = fs::read(file).with_context(|| format!("Failed to read {}", file.display()))?;
+        items.extend(rustls_pemfile::read_all(&mut pem.as_slice())?);
+    }
+    let mut certs = Vec::new();
+    let mut key = None;
+    for item in items {
+        match item {
+            Item::X509Certificate(der) => certs.push(rustls::Certificate(der)),
+            Item::PKCS8Key(der) | Item::RSAKey(der) | Item::ECKey(der) => key = key.or(Some(rustls::PrivateKey(der))),
+            _ => {}
+        }
+    }
+    match key {
+        Some(key) if !certs.is_empty() => Ok((certs, key)),
+        _ => bail!("{} needs both a certificate and its private key (or set IMMICH_CLIENT_KEY)", path.display()),
+    }
+}
+
+struct PinnedCert(Vec<u8>);
+
+impl ServerCertVerifier for PinnedCert {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = Sha256::digest(&end_entity.0);
+        if fingerprint.as_slice() == self.0 {
+            return Ok(ServerCertVerified::assertion());
+        }
+        let hex: String = fingerprint.iter().map(|b| format!("{:02x}", b)).collect();
+        Err(rustls::Error::General(format!("server certificate {} is not the pinned one", hex)))
+    }
+}
@@ -0,0 +1,92 @@
+use log::{debug, info, warn};
+use reqwest::{Client, Method};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+/// The release that pluralised the API (`/api/asset` → `/api/assets`, `/api/server-info`
+/// → `/api/server`, ...). Paths in this codebase are written for it and later.
+const PLURAL_API: (u32, u32) = (1, 106);
+
+// Endpoint prefixes of older servers, most specific first so `/api/assets` doesn't catch
+// `/api/assets/bulk-upload-check` before its own entry. An entry with a method only
+// applies to that one: uploads and bulk changes to `/api/assets` went separate ways.
+const LEGACY_PATHS: &[(Option<Method>, &str, &str)] = &[
+    (None, "/api/server/", "/api/server-info/"),
+    (None, "/api/assets/", "/api/asset/"),
+    (Some(Method::POST), "/api/assets", "/api/asset/upload"),
+    (None, "/api/assets", "/api/asset"),
+    (None, "/api/albums", "/api/album"),
+    (None, "/api/users", "/api/user"),
+];
+
+#[derive(Clone, Copy, Deserialize)]
+pub struct ServerVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl ServerVersion {
+    fn is_legacy(&self) -> bool {
+        (self.major, self.minor) < PLURAL_API
+    }
+}
+
+// Per base URL; the local and external address may well be different servers.
+static VERSIONS: LazyLock<Mutex<HashMap<String, ServerVersion>>> = LazyLock::new(Default::default);
+
+/// Asks the server for its version (once per base URL) so `url` can adapt paths to it.
+/// A server that won't say is assumed to be current.
+pub async fn detect(client: &Client, base_url: &str) {
+    if VERSIONS.lock().unwrap().contains_key(base_url) {
+        return;
+    }
+    for path in ["/api/server/version", "/api/server-info/version"] {
+        let resp = client.get(format!("{}{}", base_url, path)).timeout(Duration::from_secs(5)).send().await;
+        let Ok(resp) = resp.and_then(|r| r.error_for_status()) else {
+            continue;
+        };
+        match resp.json::<ServerVersion>().await {
+            Ok(version) => {
+                info!("Immich server v{}.{}.{}", version.major, version.minor, version.patch);
+                if version.is_legacy() {
+                    warn!(
+                        "This Immich server predates v{}.{}; using its older API paths. Stacks, tags and `trash empty` without --all need a newer server.",
+                        PLURAL_API.0, PLURAL_API.1
+                    );
+                }
+                VERSIONS.lock().unwrap().insert(base_url.to_string(), version);
+                return;
+            }
+            Err(e) => debug!("Unreadable version from {}{}: {}", base_url, path, e),
+        }
+    }
+    debug!("Could not determine the server version of {}, assuming a current one", base_url);
+}
+
+/// The full URL of `path` (written for current servers, e.g. `/api/assets/{id}`) on
+/// the server at `base_url`, rewritten for older versions.
+pub fn url(base_url: &str, path: &str) -> String {
+    rewrite(None, base_url, path)
+}
+
+/// `url` for a path that older servers served at different places depending on the
+/// method, like `POST /api/assets` (an upload).
+pub fn url_for(method: &Method, base_url: &str, path: &str) -> String {
+    rewrite(Some(method), base_url, path)
+}
+
+fn rewrite(method: Option<&Method>, base_url: &str, path: &str) -> String {
+    let legacy = VERSIONS.lock().unwrap().get(base_url).is_some_and(|v| v.is_legacy());
+    if legacy
+        && let Some((_, new, old)) = LEGACY_PATHS.iter().find(|(only, new, _)| {
+            only.as_ref().is_none_or(|only| method == Some(only))
+                && path.strip_prefix(new).is_some_and(|rest| rest.is_empty() || new.ends_with('/') || rest.starts_with(['/', '?']))
+        })
+    {
+        return format!("{}{}{}", base_url, old, &path[new.len()..]);
+    }
+    format!("{}{}", base_url, path)
+}
@@ -0,0 +1,34 @@
+use reqwest::Url;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Default for `IMMICH_MAX_CONNECTIONS_PER_HOST`.
+pub const DEFAULT_PER_HOST: usize = 4;
+
+// Shared by every client clone and every pass, so a slow server holds at most
+// its own slots and can't starve requests to another host.
+static PER_HOST: AtomicUsize = AtomicUsize::new(DEFAULT_PER_HOST);
+static HOSTS: LazyLock<Mutex<HashMap<String, Arc<Semaphore>>>> = LazyLock::new(Default::default);
+
+/// Sets how many requests may be in flight to any one host. Call before the first request.
+pub fn set_per_host(limit: usize) {
+    PER_HOST.store(limit.max(1), Ordering::Relaxed);
+}
+
+/// Waits for a free connection slot on the host of `url`; the slot is released when
+/// the permit is dropped.
+pub async fn acquire(url: &str) -> OwnedSemaphorePermit {
+    let host = match Url::parse(url) {
+        Ok(url) => format!("{}:{}", url.host_str().unwrap_or_default(), url.port_or_known_default().unwrap_or_default()),
+        Err(_) => url.to_string(),
+    };
+    let semaphore = HOSTS
+        .lock()
+        .unwrap()
+        .entry(host)
+        .or_insert_with(|| Arc::new(Semaphore::new(PER_HOST.load(Ordering::Relaxed))))
+        .clone();
+    semaphore.acquire_owned().await.expect("connection semaphores are never closed")
+}
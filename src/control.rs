@@ -0,0 +1,103 @@
+use crate::state;
+use crate::status::Status;
+use anyhow::Result;
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+const DEFAULT_SOCKET: &str = "immich_sync.sock";
+
+/// Commands accepted on the daemon's control socket, one per line.
+pub const COMMANDS: [&str; 3] = ["pause", "resume", "sync-now"];
+
+pub fn socket_path() -> PathBuf {
+    env::var_os("IMMICH_CONTROL_SOCKET").map_or_else(|| state::root().join(DEFAULT_SOCKET), PathBuf::from)
+}
+
+fn handle(command: &str, status: &Status, trigger: &Notify) -> String {
+    match command {
+        "pause" => {
+            status.pause();
+            log::info!("Uploads paused via control socket.");
+            "ok: paused".to_string()
+        }
+        "resume" => {
+            status.resume();
+            log::info!("Uploads resumed via control socket.");
+            "ok: resumed".to_string()
+        }
+        "sync-now" => {
+            trigger.notify_one();
+            "ok: sync requested".to_string()
+        }
+        other => format!("error: unknown command '{}' (expected {})", other, COMMANDS.join(", ")),
+    }
+}
+
+/// Listens on the control socket; `sync-now` wakes whoever is waiting on `trigger`.
+#[cfg(unix)]
+pub fn spawn_listener(status: Arc<Status>, trigger: Arc<Notify>) -> Result<()> {
+    use anyhow::bail;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::UnixListener;
+
+    let path = socket_path();
+    // A leftover socket from a previous run would make bind fail. One that still
+    // answers belongs to a running daemon, and removing it would cut that one off.
+    match std::os::unix::net::UnixStream::connect(&path) {
+        Ok(_) => bail!("Another instance is running (its control socket at {} answers)", path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+            let _ = std::fs::remove_file(&path);
+        }
+        Err(_) => {}
+    }
+    let listener = UnixListener::bind(&path)?;
+    log::info!("Control socket listening at {}", path.display());
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let (status, trigger) = (status.clone(), trigger.clone());
+            tokio::spawn(async move {
+                let (read, mut write) = stream.into_split();
+                let mut lines = BufReader::new(read).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let reply = handle(line.trim(), &status, &trigger);
+                    if write.write_all(format!("{}\n", reply).as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn spawn_listener(_status: Arc<Status>, _trigger: Arc<Notify>) -> Result<()> {
+    log::warn!("The control socket is only available on Unix.");
+    Ok(())
+}
+
+/// Sends one command to a running daemon and returns its reply.
+#[cfg(unix)]
+pub async fn send(command: &str) -> Result<String> {
+    use anyhow::Context;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::UnixStream;
+
+    let path = socket_path();
+    let stream = UnixStream::connect(&path)
+        .await
+        .with_context(|| format!("Could not connect to {} (is the daemon running?)", path.display()))?;
+    let (read, mut write) = stream.into_split();
+    write.write_all(format!("{}\n", command).as_bytes()).await?;
+    let mut reply = String::new();
+    BufReader::new(read).read_line(&mut reply).await?;
+    Ok(reply.trim().to_string())
+}
+
+#[cfg(not(unix))]
+pub async fn send(_command: &str) -> Result<String> {
+    anyhow::bail!("The control socket is only available on Unix.")
+}
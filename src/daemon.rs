@@ -0,0 +1,339 @@
+use crate::catchup;
+use crate::config::Config;
+use crate::control;
+use crate::health::{DEGRADED_AFTER, Health};
+use crate::metrics;
+use crate::schedule::blocked_reason;
+use crate::status::Status;
+use crate::sync::{record_pass, run_sync};
+use crate::trigger;
+use anyhow::Result;
+use log::{debug, error, info, warn};
+use notify::{EventKind, RecursiveMode, Watcher};
+use reqwest::Client;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{Notify, mpsc};
+use tokio::time::Instant;
+
+// Events arriving within this window are uploaded together.
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+// Escalation for passes that keep failing: after NOTIFY_AFTER the failure is reported
+// once (and the on-failure command runs), from BACKOFF_AFTER the interval doubles with
+// every failure up to MAX_BACKOFF times, and from health::DEGRADED_AFTER the daemon
+// reports itself degraded.
+const NOTIFY_AFTER: u32 = 3;
+const BACKOFF_AFTER: u32 = 5;
+const MAX_BACKOFF: u32 = 8;
+
+/// Repeats the sync pass forever, sleeping `interval` between passes. Files sent to
+/// the trigger FIFO are uploaded right away without waiting for the next pass.
+pub async fn run(client: &Client, config: &Config, status: Arc<Status>, interval: Duration) -> Result<()> {
+    let (tx, mut requested) = mpsc::channel::<PathBuf>(100);
+    let sync_now = spawn_listeners(config, &status, tx)?;
+    let woke = spawn_clock_watcher(config);
+
+    info!("Daemon mode: syncing every {}s.", interval.as_secs());
+    let mut next_pass = Instant::now();
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep_until(next_pass) => {
+                wait_until_allowed(config, interval).await;
+                full_pass(client, config, &status).await;
+                next_pass = Instant::now() + next_delay(&status, interval);
+            }
+            _ = sync_now.notified() => {
+                info!("Sync requested.");
+                next_pass = Instant::now();
+            }
+            _ = woke.notified() => next_pass = Instant::now(),
+            Some(path) = requested.recv() => {
+                let mut paths = vec![path];
+                while let Ok(p) = requested.try_recv() {
+                    paths.push(p);
+                }
+                // While blocked, the next regular pass picks these up
+                if blocked_reason(config).await.is_none() {
+                    sync_pass(client, config, &status, Some(paths)).await;
+                }
+            }
+        }
+    }
+}
+
+/// Uploads files as filesystem events report them. If events pile up faster than
+/// they can be uploaded (more than `backlog_limit` pending), falls back to a full
+/// scan every `interval` until the folders calm down.
+pub async fn watch(client: &Client, config: &Config, status: Arc<Status>, interval: Duration, backlog_limit: usize) -> Result<()> {
+    // Bounded so a bulk copy can't grow the queue without limit; dropped events
+    // are picked up again by the next full scan.
+    let (tx, mut rx) = mpsc::channel::<PathBuf>(backlog_limit.max(1));
+    let sync_now = spawn_listeners(config, &status, tx.clone())?;
+    let woke = spawn_clock_watcher(config);
+    let overflowed = Arc::new(AtomicBool::new(false));
+    let overflow_flag = overflowed.clone();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res
+            && matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+        {
+            for path in event.paths {
+                if tx.try_send(path).is_err() {
+                    overflow_flag.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+    })?;
+    let mode = if config.recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+    for folder in &config.folders {
+        watcher.watch(&folder.path, mode)?;
+        info!("Watching {} for new files.", folder.path.display());
+    }
+    // Catch up on anything that arrived while we weren't running
+    wait_until_allowed(config, interval).await;
+    full_pass(client, config, &status).await;
+
+    let mut batch_mode = false;
+    // Files still being written at the last check, looked at again every DEBOUNCE
+    let mut settling: BTreeSet<PathBuf> = BTreeSet::new();
+    loop {
+        // Files seen while uploads are blocked are picked up by a full scan afterwards
+        if blocked_reason(config).await.is_some() {
+            wait_until_allowed(config, interval).await;
+            while rx.try_recv().is_ok() {}
+            overflowed.store(false, Ordering::Relaxed);
+            full_pass(client, config, &status).await;
+            continue;
+        }
+
+        if batch_mode {
+            tokio::select! {
+                _ = tokio::time::sleep(next_delay(&status, interval)) => {}
+                _ = sync_now.notified() => info!("Sync requested."),
+                _ = woke.notified() => {}
+            }
+            let mut pending = 0;
+            while rx.try_recv().is_ok() {
+                pending += 1;
+            }
+            overflowed.store(false, Ordering::Relaxed);
+            full_pass(client, config, &status).await;
+            if pending < backlog_limit && rx.len() < backlog_limit {
+                info!("Backlog cleared, resuming per-event uploads.");
+                batch_mode = false;
+            }
+            continue;
+        }
+
+        let first = tokio::select! {
+            event = rx.recv() => match event {
+                Some(path) => Some(path),
+                None => break,
+            },
+            _ = tokio::time::sleep(DEBOUNCE), if !settling.is_empty() => None,
+            _ = sync_now.notified() => {
+                info!("Sync requested.");
+                full_pass(client, config, &status).await;
+                continue;
+            }
+            // Events may have been missed while asleep
+            _ = woke.notified() => {
+                full_pass(client, config, &status).await;
+                continue;
+            }
+        };
+        let mut paths = std::mem::take(&mut settling);
+        paths.extend(first);
+        let before: BTreeMap<&PathBuf, (u64, SystemTime)> = paths.iter().filter_map(|p| Some((p, snapshot(p)?))).collect();
+        tokio::time::sleep(DEBOUNCE).await;
+        let mut arrived = BTreeSet::new();
+        while let Ok(path) = rx.try_recv() {
+            arrived.insert(path);
+        }
+        // Only files whose size and mtime held still over the wait: a copy from a slow
+        // disk or a phone may take far longer than one event window
+        let mut ready = Vec::new();
+        for path in paths.iter().chain(arrived.difference(&paths)) {
+            match snapshot(path) {
+                Some(now) if before.get(path) == Some(&now) && !arrived.contains(path) => ready.push(path.clone()),
+                Some(_) => {
+                    debug!("{} is still being written, checking again shortly", path.display());
+                    settling.insert(path.clone());
+                }
+                // Gone again, e.g. a temporary file
+                None => {}
+            }
+        }
+        if ready.is_empty() || blocked_reason(config).await.is_some() {
+            continue;
+        }
+        sync_pass(client, config, &status, Some(ready)).await;
+
+        let backlog = rx.len();
+        if backlog >= backlog_limit || overflowed.load(Ordering::Relaxed) {
+            warn!(
+                "Upload backlog: {}+ file events pending. Switching to batch scans every {}s.",
+                backlog,
+                interval.as_secs()
+            );
+            batch_mode = true;
+        }
+    }
+    Ok(())
+}
+
+/// Size and modification time, for telling whether a file is still being written.
+fn snapshot(path: &Path) -> Option<(u64, SystemTime)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+/// Sleeps until uploads are allowed: inside the upload window and, if configured,
+/// off metered connections (re-checked every `interval`).
+async fn wait_until_allowed(config: &Config, interval: Duration) {
+    if let Some(window) = config.upload_window {
+        let wait = window.until_open();
+        if !wait.is_zero() {
+            info!("Outside upload window ({}), waiting {} min.", window, wait.as_secs().div_ceil(60));
+            tokio::time::sleep(wait).await;
+        }
+    }
+    let mut logged = false;
+    while let Some(reason) = blocked_reason(config).await {
+        if !logged {
+            info!("{}, deferring uploads.", reason);
+            logged = true;
+        }
+        tokio::time::sleep(interval).await;
+    }
+    if logged {
+        info!("Resuming uploads.");
+    }
+}
+
+/// A pass over all folders. After a long time without a successful pass (the machine
+/// was off, asleep or offline) a sample of earlier uploads is checked first, so files
+/// that went missing on the server in the meantime are part of this pass.
+async fn full_pass(client: &Client, config: &Config, status: &Arc<Status>) {
+    if let Some(gap) = config.catch_up_after.and_then(catchup::overdue) {
+        info!("Last successful sync was {} h ago; verifying earlier uploads before a full scan.", gap.as_secs() / 3600);
+        if let Err(e) = catchup::verify_sample(client, config).await {
+            // While the server is down the pass itself reports that
+            if status.consecutive_failures() == 0 {
+                warn!("Failed to verify earlier uploads: {:?}", e);
+            } else {
+                debug!("Failed to verify earlier uploads: {:?}", e);
+            }
+        }
+    }
+    sync_pass(client, config, status, None).await;
+}
+
+/// Notified after a long suspend; never, if catch-up is disabled.
+fn spawn_clock_watcher(config: &Config) -> Arc<Notify> {
+    config.catch_up_after.map(catchup::spawn_clock_watcher).unwrap_or_default()
+}
+
+async fn sync_pass(client: &Client, config: &Config, status: &Arc<Status>, only: Option<Vec<PathBuf>>) {
+    let before = status.consecutive_failures();
+    if let Err(e) = run_sync(client, config, status, only).await {
+        let message = format!("Sync pass failed: {}", e);
+        if status.repeats_last_failure(&message) {
+            debug!("{:?}", e);
+        } else {
+            error!("Sync pass failed: {:?}", e);
+        }
+        record_pass(status, Some(message.clone()), 0, None);
+        status.record_error(message);
+    }
+    escalate(config, before, status.consecutive_failures()).await;
+}
+
+/// Reacts to the failure count changing from `before` to `after` over one pass, so
+/// a server that stays down produces a handful of lines instead of one per interval.
+async fn escalate(config: &Config, before: u32, after: u32) {
+    if after == 0 {
+        if before >= NOTIFY_AFTER {
+            info!("Recovered after {} failed passes.", before);
+        }
+        return;
+    }
+    if before < NOTIFY_AFTER && after >= NOTIFY_AFTER {
+        error!("{} sync passes in a row have failed (repeated errors are logged at debug level).", after);
+        if let Some(command) = &config.on_failure_command {
+            run_failure_command(command, after).await;
+        }
+    }
+    if before < BACKOFF_AFTER && after >= BACKOFF_AFTER {
+        warn!("Backing off: the interval doubles with each failed pass (up to {}x).", MAX_BACKOFF);
+    }
+    if before < DEGRADED_AFTER && after >= DEGRADED_AFTER {
+        warn!("Entering degraded state after {} failed passes.", after);
+    }
+}
+
+/// The pause before the next pass: `interval`, stretched while passes keep failing.
+fn next_delay(status: &Status, interval: Duration) -> Duration {
+    let failures = status.consecutive_failures();
+    if failures < BACKOFF_AFTER {
+        return interval;
+    }
+    let factor = 1u32.checked_shl(failures - BACKOFF_AFTER + 1).unwrap_or(u32::MAX).min(MAX_BACKOFF);
+    interval * factor
+}
+
+/// Runs the user's notification hook with the failure count and last error in its environment.
+async fn run_failure_command(command: &str, failures: u32) {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = tokio::process::Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    } else {
+        let mut cmd = tokio::process::Command::new("sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    };
+    cmd.env("IMMICH_FAILURES", failures.to_string())
+        .env("IMMICH_LAST_ERROR", Health::load().last_error.unwrap_or_default());
+    match cmd.status().await {
+        Ok(status) if !status.success() => warn!("On-failure command exited with {}", status),
+        Ok(_) => {}
+        Err(e) => warn!("Failed to run on-failure command: {:?}", e),
+    }
+}
+
+/// Starts the SIGQUIT status dump, the control socket and (if configured) the trigger
+/// FIFO, which sends requested paths to `requested`. The returned handle is notified
+/// when a `sync-now` command arrives.
+fn spawn_listeners(config: &Config, status: &Arc<Status>, requested: mpsc::Sender<PathBuf>) -> Result<Arc<Notify>> {
+    #[cfg(unix)]
+    spawn_status_listener(status.clone())?;
+
+    let sync_now = Arc::new(Notify::new());
+    control::spawn_listener(status.clone(), sync_now.clone())?;
+    if let Some(addr) = config.metrics_addr {
+        metrics::spawn_server(addr, status.clone())?;
+    }
+    if let Some(fifo) = &config.trigger_fifo {
+        let roots: Vec<PathBuf> = config.folders.iter().map(|f| f.path.clone()).collect();
+        trigger::spawn_fifo_listener(fifo, &roots, requested)?;
+    }
+    Ok(sync_now)
+}
+
+// SIGQUIT (Ctrl+\) dumps the current status to the log without interrupting work.
+#[cfg(unix)]
+fn spawn_status_listener(status: Arc<Status>) -> Result<()> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut quit = signal(SignalKind::quit())?;
+    tokio::spawn(async move {
+        while quit.recv().await.is_some() {
+            status.dump();
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use crate::history::key_matches;
+use crate::state;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+
+const DEAD_LETTER_FILE: &str = "immich_dead_letters.json";
+
+#[derive(Serialize, Deserialize)]
+pub struct Failure {
+    pub attempts: u32,
+    pub last_error: String,
+    pub last_attempt: DateTime<Utc>,
+}
+
+/// Failure counts per file, by `Config::file_key`. Files that reach the retry budget are
+/// "dead letters" and are skipped until reset with `dead-letter reset`.
+#[derive(Default, Serialize, Deserialize)]
+pub struct DeadLetters {
+    files: BTreeMap<String, Failure>,
+}
+
+impl DeadLetters {
+    pub fn load() -> Self {
+        let mut dead_letters: Self = File::open(state::path(DEAD_LETTER_FILE))
+            .ok()
+            .and_then(|f| serde_json::from_reader(f).ok())
+            .unwrap_or_default();
+        // Entries by bare name can't tell same-named files apart; those get their retries back
+        dead_letters.files.retain(|key, _| key.contains('|'));
+        dead_letters
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if self.files.is_empty() && !state::path(DEAD_LETTER_FILE).exists() {
+            return Ok(());
+        }
+        serde_json::to_writer_pretty(File::create(state::path(DEAD_LETTER_FILE))?, self)?;
+        Ok(())
+    }
+
+    /// True once the file under `key` has failed `max_attempts` times (0 means retry
+    /// forever).
+    pub fn is_dead(&self, key: &str, max_attempts: u32) -> bool {
+        max_attempts > 0 && self.files.get(key).is_some_and(|f| f.attempts >= max_attempts)
+    }
+
+    pub fn record_failure(&mut self, key: &str, error: String) -> u32 {
+        let entry = self.files.entry(key.to_string()).or_insert(Failure {
+            attempts: 0,
+            last_error: String::new(),
+            last_attempt: Utc::now(),
+        });
+        entry.attempts += 1;
+        entry.last_error = error;
+        entry.last_attempt = Utc::now();
+        entry.attempts
+    }
+
+    /// Forgets the failures of the file under `key`.
+    pub fn clear(&mut self, key: &str) {
+        self.files.remove(key);
+    }
+
+    /// Forgets the failures of every file `name` picks out (see `key_matches`); returns
+    /// how many there were.
+    pub fn clear_matching(&mut self, name: &str) -> usize {
+        let before = self.files.len();
+        self.files.retain(|key, _| !key_matches(key, name));
+        before - self.files.len()
+    }
+
+    pub fn clear_all(&mut self) -> usize {
+        let n = self.files.len();
+        self.files.clear();
+        n
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Failure)> {
+        self.files.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::key_path;
+    use std::path::Path;
+
+    const A: &str = "photos.example.org|/DCIM|a/IMG_0001.JPG";
+    const B: &str = "photos.example.org|/DCIM|b/IMG_0001.JPG";
+
+    #[test]
+    fn keeps_same_named_files_apart() {
+        let mut dead_letters = DeadLetters::default();
+        dead_letters.record_failure(A, "broken".to_string());
+        dead_letters.record_failure(A, "broken".to_string());
+        dead_letters.record_failure(B, "timeout".to_string());
+        assert!(dead_letters.is_dead(A, 2));
+        assert!(!dead_letters.is_dead(B, 2));
+
+        // One uploading doesn't clear the other
+        dead_letters.clear(B);
+        assert!(dead_letters.is_dead(A, 2));
+        dead_letters.record_failure(B, "timeout".to_string());
+
+        assert_eq!(key_path(A), Path::new("/DCIM/a/IMG_0001.JPG"));
+        assert_eq!(dead_letters.clear_matching("b/IMG_0001.JPG"), 1);
+        assert!(dead_letters.is_dead(A, 2));
+        dead_letters.record_failure(B, "timeout".to_string());
+        assert_eq!(dead_letters.clear_matching("IMG_0001.JPG"), 2);
+        assert_eq!(dead_letters.iter().count(), 0);
+    }
+}
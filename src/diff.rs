@@ -0,0 +1,66 @@
+use crate::api::get_album_info;
+use crate::config::Config;
+use crate::history::History;
+use crate::scan::spawn_scan;
+use crate::sync::resolve_target;
+use anyhow::{Result, bail};
+use reqwest::Client;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashSet};
+
+#[derive(Serialize)]
+struct Row<'a> {
+    name: &'a str,
+    local: bool,
+    history: bool,
+    server: bool,
+}
+
+/// Prints, by filename, which of local folder / history / server album each file is
+/// in. Read-only: nothing is uploaded or changed.
+pub async fn run(client: &Client, config: &Config, json: bool, all: bool) -> Result<()> {
+    let target = resolve_target(client, config).await?;
+    let Some(album_id) = &target.album_id else {
+        bail!("diff compares against one album; IMMICH_ALBUM_NAME is a template or unused");
+    };
+
+    let mut local = HashSet::new();
+    let mut scan = spawn_scan(config, None);
+    while let Some(path) = scan.recv().await {
+        local.insert(path.file_name().unwrap().to_string_lossy().to_string());
+    }
+    let history: HashSet<String> = History::open()?.names()?.into_iter().collect();
+    let album = get_album_info(client, &target.base_url, &config.api_key, album_id, true).await?;
+    let server: HashSet<String> = album.assets.into_iter().map(|a| a.original_file_name).collect();
+
+    let names: BTreeSet<&str> = local.iter().chain(&history).chain(&server).map(|n| n.as_str()).collect();
+    let rows: Vec<Row> = names
+        .into_iter()
+        .map(|name| Row {
+            name,
+            local: local.contains(name),
+            history: history.contains(name),
+            server: server.contains(name),
+        })
+        .filter(|r| all || !(r.local && r.history && r.server))
+        .collect();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+        return Ok(());
+    }
+
+    let mark = |b: bool| if b { "x" } else { "-" };
+    println!("{:<6} {:<8} {:<7} NAME", "LOCAL", "HISTORY", "SERVER");
+    for row in &rows {
+        println!("{:<6} {:<8} {:<7} {}", mark(row.local), mark(row.history), mark(row.server), row.name);
+    }
+    println!(
+        "\n{} local, {} in history, {} in album; {} shown",
+        local.len(),
+        history.len(),
+        server.len(),
+        rows.len()
+    );
+    Ok(())
+}
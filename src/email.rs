@@ -0,0 +1,87 @@
+use crate::config::{EmailSettings, SmtpSecurity};
+use crate::summary::{Summary, escape_html};
+use anyhow::{Context, Result};
+use indicatif::HumanBytes;
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+/// Emails the summary as a table, with every failed file, to `IMMICH_EMAIL_TO`.
+pub async fn send(settings: &EmailSettings, subject: &str, summary: Option<&Summary>, failure: Option<&str>) -> Result<()> {
+    let from: Mailbox = settings.from.parse().context("Invalid IMMICH_EMAIL_FROM")?;
+    let mut message = Message::builder().from(from).subject(subject);
+    for to in &settings.to {
+        message = message.to(to.parse().with_context(|| format!("Invalid address '{}' in IMMICH_EMAIL_TO", to))?);
+    }
+    let message = message.multipart(MultiPart::alternative_plain_html(plain(summary, failure), html(subject, summary, failure)))?;
+
+    let mut transport = match settings.security {
+        SmtpSecurity::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&settings.host)?,
+        SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.host)?,
+        SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host),
+    };
+    if let Some(port) = settings.port {
+        transport = transport.port(port);
+    }
+    if let Some(user) = &settings.user {
+        transport = transport.credentials(Credentials::new(user.clone(), settings.password.clone().unwrap_or_default()));
+    }
+    transport
+        .build()
+        .send(message)
+        .await
+        .with_context(|| format!("Could not send the report through {}", settings.host))?;
+    Ok(())
+}
+
+fn plain(summary: Option<&Summary>, failure: Option<&str>) -> String {
+    let mut text = String::new();
+    if let Some(s) = summary {
+        text.push_str(&format!("Run {}\n{}\n", s.run_id, s));
+        if !s.failures.is_empty() {
+            text.push_str("\nFailed files:\n");
+            for (file, error) in &s.failures {
+                text.push_str(&format!("{}: {}\n", file, error));
+            }
+        }
+    }
+    if let Some(error) = failure {
+        text.push_str(&format!("\nLast error: {}\n", error));
+    }
+    text
+}
+
+fn html(subject: &str, summary: Option<&Summary>, failure: Option<&str>) -> String {
+    let mut page = format!("<html><body style=\"font-family: sans-serif\">\n<h2>{}</h2>\n", escape_html(subject));
+    if let Some(s) = summary {
+        let rows = [
+            ("Run", s.run_id.clone()),
+            ("Scanned", s.scanned.to_string()),
+            ("In history", s.in_history.to_string()),
+            ("Already on server", s.duplicates.to_string()),
+            ("Uploaded", s.uploaded.to_string()),
+            ("Failed", s.failed.to_string()),
+            ("Skipped", s.skipped.to_string()),
+            ("Transferred", HumanBytes(s.bytes).to_string()),
+            ("Took", format!("{:.0}s", s.elapsed.as_secs_f64())),
+        ];
+        page.push_str("<table cellpadding=\"4\">\n");
+        for (name, value) in rows {
+            page.push_str(&format!("<tr><th align=\"left\">{}</th><td>{}</td></tr>\n", name, escape_html(&value)));
+        }
+        page.push_str("</table>\n");
+        if !s.failures.is_empty() {
+            page.push_str("<h3>Failed files</h3>\n<table cellpadding=\"4\" border=\"1\" style=\"border-collapse: collapse\">\n");
+            page.push_str("<tr><th>File</th><th>Error</th></tr>\n");
+            for (file, error) in &s.failures {
+                page.push_str(&format!("<tr><td>{}</td><td>{}</td></tr>\n", escape_html(file), escape_html(error)));
+            }
+            page.push_str("</table>\n");
+        }
+    }
+    if let Some(error) = failure {
+        page.push_str(&format!("<p><b>Last error:</b> {}</p>\n", escape_html(error)));
+    }
+    page.push_str("</body></html>\n");
+    page
+}
@@ -0,0 +1,100 @@
+use reqwest::{Response, StatusCode};
+use std::error::Error as _;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Why a request or an upload failed, by class, so callers can decide whether to
+/// retry, dead-letter or stop without matching on message text. Functions still
+/// return `anyhow::Result`; `classify` finds the `SyncError` behind one.
+#[derive(Debug, Error)]
+pub enum SyncError {
+    /// The server couldn't be reached, or the connection dropped or stalled.
+    #[error("{message}")]
+    Network { path: Option<PathBuf>, message: String },
+    /// The server refused the API key or session.
+    #[error("Status {status} - {message}")]
+    Auth { status: StatusCode, message: String },
+    /// The user's storage quota (or the server's disk) is full.
+    #[error("Status {status} - {message}")]
+    Quota { path: Option<PathBuf>, status: StatusCode, message: String },
+    /// The file can't go up as it is: empty, a format the server refuses, or a location
+    /// that can't be stripped.
+    #[error("{message}")]
+    UnsupportedMedia { path: PathBuf, status: Option<StatusCode>, message: String },
+    /// Any other error status.
+    #[error("Status {status} - {message}")]
+    Server { path: Option<PathBuf>, status: StatusCode, message: String },
+    /// Reading the local file failed.
+    #[error("{}: {error}", path.display())]
+    Io { path: PathBuf, error: std::io::Error },
+}
+
+impl SyncError {
+    /// Reads a failed response into the matching variant, keeping the server's message.
+    pub async fn from_response(resp: Response, path: Option<&Path>) -> Self {
+        let status = resp.status();
+        let message = resp.text().await.unwrap_or_default();
+        // Immich answers both of these with a plain 400
+        let quota = status == StatusCode::INSUFFICIENT_STORAGE || message.contains("Quota has been exceeded");
+        let unsupported = status == StatusCode::UNSUPPORTED_MEDIA_TYPE || message.contains("Unsupported file type");
+        match (status, path.map(Path::to_path_buf)) {
+            (StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN, _) => Self::Auth { status, message },
+            (_, path) if quota => Self::Quota { path, status, message },
+            (_, Some(path)) if unsupported => Self::UnsupportedMedia { path, status: Some(status), message },
+            (_, path) => Self::Server { path, status, message },
+        }
+    }
+
+    /// A request that never got a response (or got an error status via `error_for_status`).
+    pub fn network(error: reqwest::Error, path: Option<&Path>) -> Self {
+        let path = path.map(Path::to_path_buf);
+        if let Some(status) = error.status() {
+            return Self::Server { path, status, message: error.to_string() };
+        }
+        // reqwest keeps the useful part ("connection refused") in the source chain
+        let mut message = error.to_string();
+        let mut source = error.source();
+        while let Some(cause) = source {
+            message.push_str(&format!(": {}", cause));
+            source = cause.source();
+        }
+        Self::Network { path, message }
+    }
+
+    pub fn io(path: &Path, error: std::io::Error) -> Self {
+        Self::Io { path: path.to_path_buf(), error }
+    }
+
+    pub fn unsupported(path: &Path, message: impl Into<String>) -> Self {
+        Self::UnsupportedMedia { path: path.to_path_buf(), status: None, message: message.into() }
+    }
+
+    /// The file the error is about, if any.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::Network { path, .. } | Self::Quota { path, .. } | Self::Server { path, .. } => path.as_deref(),
+            Self::UnsupportedMedia { path, .. } | Self::Io { path, .. } => Some(path),
+            Self::Auth { .. } => None,
+        }
+    }
+
+    /// The HTTP status the server answered with, if it answered.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Self::Auth { status, .. } | Self::Quota { status, .. } | Self::Server { status, .. } => Some(*status),
+            Self::UnsupportedMedia { status, .. } => *status,
+            Self::Network { .. } | Self::Io { .. } => None,
+        }
+    }
+
+    /// Whether the failure says nothing about the file itself: the connection, the
+    /// credentials or the quota. Such failures don't count towards dead-lettering.
+    pub fn is_environmental(&self) -> bool {
+        matches!(self, Self::Network { .. } | Self::Auth { .. } | Self::Quota { .. })
+    }
+}
+
+/// The `SyncError` behind an `anyhow` error, through any context added since.
+pub fn classify(error: &anyhow::Error) -> Option<&SyncError> {
+    error.chain().find_map(|cause| cause.downcast_ref::<SyncError>())
+}
@@ -0,0 +1,92 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use log::{debug, warn};
+use std::fs;
+use std::path::Path;
+
+/// Timestamped positions from every `.gpx` file in a directory, merged and sorted.
+pub struct Tracks {
+    points: Vec<(DateTime<Utc>, f64, f64)>,
+}
+
+impl Tracks {
+    pub fn load_dir(dir: &Path) -> Result<Self> {
+        let mut points = Vec::new();
+        for entry in fs::read_dir(dir).with_context(|| format!("Failed to read GPX directory {}", dir.display()))? {
+            let path = entry?.path();
+            if !path.extension().is_some_and(|e| e.eq_ignore_ascii_case("gpx")) {
+                continue;
+            }
+            match fs::read_to_string(&path) {
+                Ok(text) => {
+                    let before = points.len();
+                    points.extend(parse_points(&text));
+                    debug!("Loaded {} track points from {}", points.len() - before, path.display());
+                }
+                Err(e) => warn!("Skipping GPX file {}: {}", path.display(), e),
+            }
+        }
+        points.sort_by_key(|p| p.0);
+        Ok(Self { points })
+    }
+
+    /// Position at `at`, linearly interpolated between the surrounding points. Nothing
+    /// is returned if the nearest recorded point is more than `max_gap` away.
+    pub fn locate(&self, at: DateTime<Utc>, max_gap: Duration) -> Option<(f64, f64)> {
+        let i = self.points.partition_point(|p| p.0 < at);
+        let after = self.points.get(i);
+        let before = i.checked_sub(1).and_then(|j| self.points.get(j));
+        match (before, after) {
+            (Some(&(t0, lat0, lon0)), Some(&(t1, lat1, lon1))) if t1 - t0 <= max_gap * 2 => {
+                let span = (t1 - t0).num_milliseconds();
+                let f = if span == 0 { 0.0 } else { (at - t0).num_milliseconds() as f64 / span as f64 };
+                Some((lat0 + (lat1 - lat0) * f, lon0 + (lon1 - lon0) * f))
+            }
+            (before, after) => [before, after]
+                .into_iter()
+                .flatten()
+                .filter(|p| (p.0 - at).abs() <= max_gap)
+                .min_by_key(|p| (p.0 - at).abs())
+                .map(|&(_, lat, lon)| (lat, lon)),
+        }
+    }
+}
+
+/// Pulls `<trkpt lat=".." lon=".."><time>..</time></trkpt>` (and `rtept`/`wpt`) out of a
+/// GPX document. Points without a time are useless for matching and are skipped.
+fn parse_points(text: &str) -> Vec<(DateTime<Utc>, f64, f64)> {
+    let mut points = Vec::new();
+    for name in ["trkpt", "rtept", "wpt"] {
+        let (open, close) = (format!("<{}", name), format!("</{}>", name));
+        let mut rest = text;
+        while let Some(start) = rest.find(&open) {
+            rest = &rest[start + open.len()..];
+            if !rest.starts_with(char::is_whitespace) {
+                continue;
+            }
+            let Some(tag_end) = rest.find('>') else {
+                break;
+            };
+            let (tag, after) = rest.split_at(tag_end);
+            let body = if tag.ends_with('/') { "" } else { &after[..after.find(&close).unwrap_or(0)] };
+            let time = body
+                .split_once("<time>")
+                .and_then(|(_, t)| t.split_once("</time>"))
+                .and_then(|(t, _)| DateTime::parse_from_rfc3339(t.trim()).ok());
+            if let (Some(lat), Some(lon), Some(time)) = (attribute(tag, "lat"), attribute(tag, "lon"), time) {
+                points.push((time.with_timezone(&Utc), lat, lon));
+            }
+            rest = after;
+        }
+    }
+    points
+}
+
+fn attribute(tag: &str, name: &str) -> Option<f64> {
+    let key = format!("{}=", name);
+    let (at, _) = tag.match_indices(&key).find(|&(i, _)| tag[..i].ends_with(char::is_whitespace))?;
+    let rest = &tag[at + key.len()..];
+    let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let value = &rest[1..];
+    value[..value.find(quote)?].parse().ok()
+}
@@ -0,0 +1,212 @@
+use crate::api::Uploader;
+use crate::error::SyncError;
+use crate::metadata::{self, has_gps, read_exif_bytes};
+use crate::scan::{RAW_EXTENSIONS, VIDEO_EXTENSIONS, has_extension, jpeg_for_raw, live_photo_video_for, sidecar_for};
+use crate::transform::{self, HeicToJpeg};
+use anyhow::Result;
+use bytes::Bytes;
+use chrono::{DateTime, FixedOffset};
+use futures_util::future::BoxFuture;
+use log::{info, warn};
+use mime_guess::mime::{self, Mime};
+use std::path::{Path, PathBuf};
+
+/// The file as it goes over the wire.
+pub struct Payload {
+    pub contents: Contents,
+    pub name: String,
+    pub mime: Mime,
+}
+
+/// Where the bytes of a `Payload` come from.
+pub enum Contents {
+    /// The file itself, read from disk as the request goes out.
+    File(PathBuf),
+    /// What the file was turned into: converted, downscaled or stripped of GPS.
+    Bytes(Bytes),
+}
+
+/// Everything format-specific about uploading one class of asset. `Uploader::upload_asset`
+/// only builds and sends the request; it asks the file's handler what to send.
+pub trait Handler: Send + Sync {
+    /// Shown in logs, e.g. "RAW photo".
+    fn kind(&self) -> &'static str;
+
+    /// Refuses files that can't go up as they are, before any request is made.
+    fn validate(&self, path: &Path, size: u64) -> Result<()> {
+        if size == 0 {
+            return Err(SyncError::unsupported(path, format!("{} is empty", path.display())).into());
+        }
+        Ok(())
+    }
+
+    /// The capture date sent as `fileCreatedAt`, if the file records one.
+    fn taken_at(&self, path: &Path, date_patterns: &[String]) -> Option<DateTime<FixedOffset>> {
+        metadata::taken_at(path, date_patterns)
+    }
+
+    /// Turns the file into what gets uploaded.
+    fn prepare<'a>(&'a self, path: &'a Path, _uploader: &'a Uploader) -> BoxFuture<'a, Result<Payload>> {
+        Box::pin(async move { Ok(as_is(path)) })
+    }
+
+    /// An XMP sidecar to send in the same request.
+    fn sidecar(&self, path: &Path, uploader: &Uploader) -> Option<PathBuf> {
+        let sidecar = sidecar_for(path)?;
+        // With GPS stripping on, a sidecar carrying a location would put it back
+        if uploader.strip_gps && metadata::xmp_has_gps(&std::fs::read(&sidecar).ok()?) {
+            return None;
+        }
+        Some(sidecar)
+    }
+
+    /// A file to upload first and link to this one (a Live Photo's video).
+    fn companion(&self, _path: &Path) -> Option<PathBuf> {
+        None
+    }
+
+    /// A file this one is stacked under once both are uploaded (RAW+JPEG pairs).
+    fn stack_under(&self, _path: &Path) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Photos and screenshots: converted, downscaled and stripped of GPS as configured.
+pub struct Image;
+/// A still whose video half sits next to it; the video goes up first and gets paired.
+pub struct LivePhoto;
+/// Camera RAW files, uploaded untouched and stacked under the JPEG shot with them.
+pub struct Raw;
+pub struct Video;
+
+/// Picks the handler for `path`. Checked in order, so a still with a Live Photo video
+/// is a `LivePhoto` rather than a plain `Image`.
+pub fn handler_for(path: &Path) -> &'static dyn Handler {
+    if live_photo_video_for(path).is_some() {
+        &LivePhoto
+    } else if has_extension(path, RAW_EXTENSIONS) {
+        &Raw
+    } else if has_extension(path, VIDEO_EXTENSIONS) {
+        &Video
+    } else {
+        &Image
+    }
+}
+
+impl Handler for Image {
+    fn kind(&self) -> &'static str {
+        "photo"
+    }
+
+    fn prepare<'a>(&'a self, path: &'a Path, uploader: &'a Uploader) -> BoxFuture<'a, Result<Payload>> {
+        Box::pin(prepare_image(path, uploader))
+    }
+}
+
+impl Handler for LivePhoto {
+    fn kind(&self) -> &'static str {
+        "Live Photo"
+    }
+
+    fn prepare<'a>(&'a self, path: &'a Path, uploader: &'a Uploader) -> BoxFuture<'a, Result<Payload>> {
+        Box::pin(prepare_image(path, uploader))
+    }
+
+    fn companion(&self, path: &Path) -> Option<PathBuf> {
+        live_photo_video_for(path)
+    }
+}
+
+impl Handler for Raw {
+    fn kind(&self) -> &'static str {
+        "RAW photo"
+    }
+
+    fn prepare<'a>(&'a self, path: &'a Path, uploader: &'a Uploader) -> BoxFuture<'a, Result<Payload>> {
+        Box::pin(async move {
+            let payload = as_is(path);
+            if uploader.strip_gps && metadata::read_exif(path).is_some_and(|exif| has_gps(&exif)) {
+                return Err(refused(path, &payload.name));
+            }
+            Ok(payload)
+        })
+    }
+
+    fn stack_under(&self, path: &Path) -> Option<PathBuf> {
+        jpeg_for_raw(path)
+    }
+}
+
+impl Handler for Video {
+    fn kind(&self) -> &'static str {
+        "video"
+    }
+}
+
+fn as_is(path: &Path) -> Payload {
+    Payload {
+        contents: Contents::File(path.to_path_buf()),
+        name: path.file_name().unwrap().to_string_lossy().to_string(),
+        mime: mime_guess::from_path(path).first_or_octet_stream(),
+    }
+}
+
+async fn prepare_image(path: &Path, uploader: &Uploader) -> Result<Payload> {
+    let mut payload = as_is(path);
+    // Only a file that may be changed is read into memory; the rest streams from disk
+    if uploader.downscale.is_none() && uploader.heic_to_jpeg.is_none() && !uploader.strip_gps {
+        return Ok(payload);
+    }
+    let mut bytes = tokio::fs::read(path).await.map_err(|e| SyncError::io(path, e))?;
+    let filename = payload.name.clone();
+    // Converting or re-encoding would drop the embedded video
+    let motion = (uploader.downscale.is_some() || uploader.heic_to_jpeg.is_some()) && metadata::is_motion_photo(&bytes);
+    if motion {
+        info!("   -- {} is a Motion Photo, uploading it unchanged", filename);
+    }
+    if let Some(transcode) = uploader.heic_to_jpeg.as_ref().filter(|_| !motion)
+        && HeicToJpeg::applies_to(path)
+    {
+        match transcode.convert(path).await {
+            Ok(jpeg) => {
+                bytes = jpeg;
+                payload.name = Path::new(&filename).with_extension("jpg").to_string_lossy().to_string();
+                payload.mime = mime::IMAGE_JPEG;
+            }
+            Err(e) => warn!("Uploading {} as HEIC: {:?}", filename, e),
+        }
+    }
+    if let Some(downscale) = uploader.downscale.filter(|_| !motion) {
+        let downscaled;
+        (bytes, downscaled) = tokio::task::spawn_blocking(move || {
+            let downscaled = downscale.apply(&bytes);
+            (bytes, downscaled)
+        })
+        .await?;
+        match downscaled {
+            Ok(Some(jpeg)) => {
+                info!("   -- Downscaled {} ({} -> {} KB)", filename, bytes.len() / 1024, jpeg.len() / 1024);
+                bytes = jpeg;
+                payload.name = Path::new(&filename).with_extension("jpg").to_string_lossy().to_string();
+                payload.mime = mime::IMAGE_JPEG;
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Could not downscale {}, uploading the original: {:?}", filename, e),
+        }
+    }
+    if uploader.strip_gps {
+        if transform::strip_gps(&mut bytes) {
+            info!("   -- Removed location from {}", filename);
+        }
+        if read_exif_bytes(&bytes).is_some_and(|exif| has_gps(&exif)) || metadata::xmp_has_gps(&bytes) {
+            return Err(refused(path, &payload.name));
+        }
+    }
+    payload.contents = Contents::Bytes(bytes.into());
+    Ok(payload)
+}
+
+/// With GPS stripping on, a file that still has a location doesn't go up at all.
+fn refused(path: &Path, name: &str) -> anyhow::Error {
+    SyncError::unsupported(path, format!("Can't remove the location from {}, not uploading it", name)).into()
+}
@@ -0,0 +1,82 @@
+use crate::run;
+use crate::state;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::{self, File};
+use std::path::PathBuf;
+
+const DEFAULT_HEALTH_FILE: &str = "immich_health.json";
+/// Consecutive failed passes after which the uploader reports itself as degraded.
+pub const DEGRADED_AFTER: u32 = 10;
+
+fn health_path() -> PathBuf {
+    state::path(env::var("IMMICH_HEALTH_FILE").unwrap_or_else(|_| DEFAULT_HEALTH_FILE.to_string()))
+}
+
+/// Machine-readable summary of recent runs, rewritten after every pass so that external
+/// monitors can alert on trends (e.g. `consecutive_failures > 3`) without parsing logs.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Health {
+    pub last_run: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub last_run_id: String,
+    pub last_success: Option<DateTime<Utc>>,
+    pub consecutive_failures: u32,
+    /// Files found locally but not uploaded yet, as of the last full scan
+    pub backlog: usize,
+    pub last_uploaded: usize,
+    pub last_error: Option<String>,
+    #[serde(default)]
+    pub degraded: bool,
+}
+
+impl Health {
+    pub fn load() -> Self {
+        File::open(health_path())
+            .ok()
+            .and_then(|f| serde_json::from_reader(f).ok())
+            .unwrap_or_default()
+    }
+
+    /// Written to a temporary file first so readers never see a half-written snapshot.
+    pub fn save(&self) -> Result<()> {
+        let path = health_path();
+        let tmp = path.with_extension("json.tmp");
+        serde_json::to_writer_pretty(File::create(&tmp)?, self)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Records the outcome of a pass and returns the number of consecutive failures.
+/// `backlog` is `None` for partial passes (watch events, trigger requests), which
+/// can't tell how much is still pending overall.
+pub fn record_run(error: Option<String>, uploaded: usize, backlog: Option<usize>) -> u32 {
+    let mut health = Health::load();
+    let now = Utc::now();
+    health.last_run = Some(now);
+    health.last_run_id = run::current();
+    health.last_uploaded = uploaded;
+    if let Some(backlog) = backlog {
+        health.backlog = backlog;
+    }
+    match error {
+        Some(e) => {
+            health.consecutive_failures += 1;
+            health.last_error = Some(e);
+        }
+        None => {
+            health.last_success = Some(now);
+            health.consecutive_failures = 0;
+            health.last_error = None;
+        }
+    }
+    health.degraded = health.consecutive_failures >= DEGRADED_AFTER;
+    if let Err(e) = health.save() {
+        warn!("Failed to write health file: {:?}", e);
+    }
+    health.consecutive_failures
+}
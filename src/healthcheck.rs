@@ -0,0 +1,25 @@
+use crate::notify::{self, CLIENT};
+use crate::summary::Summary;
+use log::warn;
+
+/// healthchecks.io signal: `/start` as a full pass begins, so overlong runs show up too.
+pub async fn start(url: &str) {
+    ping(&format!("{}/start", url), String::new()).await;
+}
+
+/// Success (the check URL itself) or `/fail`, with the run summary and the error as
+/// the body so the notification says what happened.
+pub async fn finish(url: &str, summary: Option<&Summary>, failure: Option<&str>) {
+    let body = notify::report(summary, failure);
+    match failure {
+        Some(_) => ping(&format!("{}/fail", url), body).await,
+        None => ping(url, body).await,
+    }
+}
+
+async fn ping(url: &str, body: String) {
+    let result = CLIENT.post(url).body(body).send().await.and_then(|r| r.error_for_status());
+    if let Err(e) = result {
+        warn!("Healthcheck ping to {} failed: {}", url, e);
+    }
+}
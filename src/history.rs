@@ -0,0 +1,646 @@
+use crate::config::{Config, SourceFolder};
+use crate::state;
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::instrument;
+
+const STATE_DB: &str = "immich_state.db";
+/// The JSON history of earlier versions, imported into `STATE_DB` once.
+const HISTORY_FILE: &str = "immich_upload_history.json";
+const SCHEMA_VERSION: i32 = 5;
+/// The database is copied to `<STATE_DB>.bak` at most this often.
+const BACKUP_INTERVAL: Duration = Duration::from_secs(24 * 3600);
+
+// Older versions stored a bare list of filenames; those entries are kept with no hash.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HistoryEntry {
+    Tracked { name: String, sha1: String },
+    Legacy(String),
+}
+
+/// What identifies a file to the history: its bytes, not its name.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Content {
+    pub sha1: String,
+    pub size: u64,
+}
+
+/// An uploaded file as recorded in the history. Entries from older versions may have
+/// only the name.
+#[derive(Serialize, Deserialize)]
+pub struct Record {
+    /// The folder and server it was uploaded for (`Config::job_for`); empty for entries
+    /// from before jobs, which any job may claim
+    #[serde(default)]
+    pub job: String,
+    pub name: String,
+    /// Relative to the synced folder it was found in, `/`-separated
+    pub path: Option<String>,
+    pub sha1: Option<String>,
+    pub size: Option<u64>,
+    /// Seconds since the epoch
+    pub mtime: Option<i64>,
+    pub uploaded_at: DateTime<Utc>,
+    /// `None` when the server took the file without saying which asset it became
+    pub asset_id: Option<String>,
+    /// The albums it was added to, comma-separated
+    pub album_id: Option<String>,
+}
+
+impl Record {
+    pub fn new(config: &Config, path: &Path, content: Content, asset_id: Option<String>) -> Self {
+        let mtime = fs::metadata(path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64);
+        Self {
+            job: config.job_for(path),
+            name: path.file_name().unwrap().to_string_lossy().to_string(),
+            path: Some(relative_path(path, &config.folders)),
+            sha1: Some(content.sha1),
+            size: Some(content.size),
+            mtime,
+            uploaded_at: Utc::now(),
+            asset_id,
+            album_id: None,
+        }
+    }
+}
+
+/// `path` relative to the synced folder it is in, `/`-separated.
+pub fn relative_path(path: &Path, roots: &[SourceFolder]) -> String {
+    let relative = roots.iter().find_map(|root| path.strip_prefix(&root.path).ok()).unwrap_or(path);
+    relative.to_string_lossy().replace('\\', "/")
+}
+
+/// The local file a `Config::file_key` stands for; a bare name for keys from before those.
+pub fn key_path(key: &str) -> PathBuf {
+    match key.splitn(3, '|').collect::<Vec<_>>()[..] {
+        [_, folder, relative] => Path::new(folder).join(relative),
+        _ => PathBuf::from(key),
+    }
+}
+
+/// Whether `name`, as given on the command line, picks out the file under `key`: the key
+/// itself, the local path, the path under its folder or just the file name.
+pub fn key_matches(key: &str, name: &str) -> bool {
+    let relative = key.rsplit_once('|').map_or(key, |(_, relative)| relative);
+    key == name || key_path(key) == Path::new(name) || relative == name || relative.rsplit('/').next() == Some(name)
+}
+
+const RECORD_COLUMNS: &str = "name, path, sha1, size, mtime, recorded_at, asset_id, album_id, job";
+
+fn record(row: &rusqlite::Row) -> rusqlite::Result<Record> {
+    let recorded_at: String = row.get(5)?;
+    Ok(Record {
+        name: row.get(0)?,
+        path: row.get(1)?,
+        sha1: row.get(2)?,
+        size: row.get(3)?,
+        mtime: row.get(4)?,
+        uploaded_at: DateTime::parse_from_rfc3339(&recorded_at).map(|t| t.to_utc()).unwrap_or_default(),
+        asset_id: row.get(6)?,
+        album_id: row.get(7)?,
+        job: row.get(8)?,
+    })
+}
+
+fn connect(path: &Path) -> Result<Connection> {
+    let db = Connection::open(path)?;
+    // The daemon and a CLI command may have it open at the same time
+    db.busy_timeout(Duration::from_secs(5))?;
+    db.pragma_update(None, "journal_mode", "WAL")?;
+    // Fails on a damaged header or schema
+    db.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))?;
+    Ok(db)
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    path.with_extension("db.bak")
+}
+
+/// Moves the damaged database (and its WAL) aside and opens a copy of the backup.
+fn restore_backup(path: &Path, error: anyhow::Error) -> Result<Connection> {
+    let backup = backup_path(path);
+    if !backup.exists() {
+        bail!("{} is damaged and there is no backup; move it away to start a new history: {:#}", path.display(), error);
+    }
+    warn!("{} is damaged ({:#}), restoring {}", path.display(), error, backup.display());
+    fs::rename(path, path.with_extension("db.damaged"))?;
+    for wal in ["db-wal", "db-shm"] {
+        let _ = fs::remove_file(path.with_extension(wal));
+    }
+    fs::copy(&backup, path)?;
+    connect(path).with_context(|| format!("Could not open the restored {}", path.display()))
+}
+
+/// Uploaded content, with the name it was last seen under. Backed by SQLite: lookups use
+/// the indexes and every change is written (and committed) right away.
+pub struct History {
+    db: Connection,
+}
+
+impl History {
+    /// Opens (or creates) the state database, importing a JSON history left by an
+    /// older version the first time. A damaged database is replaced by its backup rather
+    /// than started over, which would upload everything again.
+    pub fn open() -> Result<Self> {
+        let path = state::path(STATE_DB);
+        let db = match connect(&path) {
+            Ok(db) => db,
+            Err(e) => restore_backup(&path, e)?,
+        };
+        let mut history = Self { db };
+        history.migrate()?;
+        history.import_json()?;
+        if let Err(e) = history.backup_if_due(&path) {
+            warn!("Failed to back up {}: {:?}", path.display(), e);
+        }
+        Ok(history)
+    }
+
+    /// Refreshes `<STATE_DB>.bak` once it's `BACKUP_INTERVAL` old. The copy is made next
+    /// to it and renamed into place, so there's always one complete backup.
+    fn backup_if_due(&self, path: &Path) -> Result<()> {
+        let backup = backup_path(path);
+        let age = fs::metadata(&backup).and_then(|m| m.modified()).map(|t| SystemTime::now().duration_since(t).unwrap_or_default());
+        if age.is_ok_and(|age| age < BACKUP_INTERVAL) {
+            return Ok(());
+        }
+        let tmp = backup.with_extension("bak.tmp");
+        let _ = fs::remove_file(&tmp);
+        self.db.execute("VACUUM INTO ?1", [tmp.to_string_lossy()])?;
+        fs::rename(tmp, backup)?;
+        Ok(())
+    }
+
+    /// Brings the schema up to `SCHEMA_VERSION`, one version at a time.
+    fn migrate(&mut self) -> Result<()> {
+        let version: i32 = self.db.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        let tx = self.db.transaction()?;
+        if version < 1 {
+            tx.execute_batch(
+                "CREATE TABLE uploads (
+                    name TEXT PRIMARY KEY,
+                    sha1 TEXT,
+                    recorded_at TEXT NOT NULL
+                );",
+            )?;
+        }
+        // 2: keyed by content (SHA-1 and size); entries without a hash keep only their name
+        if version < 2 {
+            tx.execute_batch(
+                "ALTER TABLE uploads RENAME TO uploads_v1;
+                CREATE TABLE uploads (
+                    id INTEGER PRIMARY KEY,
+                    sha1 TEXT,
+                    size INTEGER,
+                    name TEXT NOT NULL,
+                    recorded_at TEXT NOT NULL
+                );
+                INSERT OR IGNORE INTO uploads (sha1, name, recorded_at) SELECT sha1, name, recorded_at FROM uploads_v1;
+                DROP TABLE uploads_v1;
+                DROP INDEX IF EXISTS uploads_sha1;
+                CREATE UNIQUE INDEX uploads_content ON uploads (sha1, size);
+                CREATE INDEX uploads_name ON uploads (name);",
+            )?;
+        }
+        // 3: where the file was, its mtime, and the asset and album it ended up in
+        if version < 3 {
+            tx.execute_batch(
+                "ALTER TABLE uploads ADD COLUMN path TEXT;
+                ALTER TABLE uploads ADD COLUMN mtime INTEGER;
+                ALTER TABLE uploads ADD COLUMN asset_id TEXT;
+                ALTER TABLE uploads ADD COLUMN album_id TEXT;
+                CREATE INDEX uploads_asset ON uploads (asset_id);",
+            )?;
+        }
+        // 4: uploads are kept per job, so the same file can go to two folders' albums
+        if version < 4 {
+            tx.execute_batch(
+                "ALTER TABLE uploads ADD COLUMN job TEXT NOT NULL DEFAULT '';
+                DROP INDEX uploads_content;
+                CREATE UNIQUE INDEX uploads_content ON uploads (job, sha1, size);",
+            )?;
+        }
+        // 5: hashes of local files, so unchanged ones aren't read again each run
+        if version < 5 {
+            tx.execute_batch(
+                "CREATE TABLE checksums (
+                    path TEXT PRIMARY KEY,
+                    size INTEGER NOT NULL,
+                    mtime_ns INTEGER NOT NULL,
+                    sha1 TEXT NOT NULL
+                );",
+            )?;
+        }
+        tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn import_json(&mut self) -> Result<()> {
+        let json = state::path(HISTORY_FILE);
+        if !json.exists() {
+            return Ok(());
+        }
+        // Importing nothing would make the next sync upload everything again
+        let entries: Vec<HistoryEntry> = serde_json::from_reader(File::open(&json)?)
+            .with_context(|| format!("{} is damaged; fix or remove it", json.display()))?;
+        let tx = self.db.transaction()?;
+        for entry in &entries {
+            let (name, sha1) = match entry {
+                HistoryEntry::Tracked { name, sha1 } => (name, Some(sha1)),
+                HistoryEntry::Legacy(name) => (name, None),
+            };
+            tx.execute(
+                "INSERT OR IGNORE INTO uploads (sha1, name, recorded_at) VALUES (?1, ?2, ?3)",
+                params![sha1, name, Utc::now().to_rfc3339()],
+            )?;
+        }
+        tx.commit()?;
+        // Kept for reference, but out of the way so it isn't imported again
+        fs::rename(&json, json.with_extension("json.migrated"))?;
+        info!("Moved {} upload history entries from {} to {}", entries.len(), HISTORY_FILE, STATE_DB);
+        Ok(())
+    }
+
+    /// The content of a local file; hashed only if its size or mtime changed since the
+    /// last time.
+    pub fn content(&mut self, path: &Path) -> Result<Content> {
+        let (size, mtime_ns) = stamp(path)?;
+        if let Some(content) = self.cached(path, size, mtime_ns)? {
+            return Ok(content);
+        }
+        self.store(path, size, mtime_ns, hash_file(path)?)
+    }
+
+    /// `content` for the upload loop: the hashing runs on a blocking thread rather than
+    /// holding up the runtime.
+    pub async fn content_async(&mut self, path: &Path) -> Result<Content> {
+        let (size, mtime_ns) = stamp(path)?;
+        if let Some(content) = self.cached(path, size, mtime_ns)? {
+            return Ok(content);
+        }
+        let owned = path.to_path_buf();
+        let sha1 = tokio::task::spawn_blocking(move || hash_file(&owned)).await??;
+        self.store(path, size, mtime_ns, sha1)
+    }
+
+    fn cached(&self, path: &Path, size: u64, mtime_ns: i64) -> Result<Option<Content>> {
+        let sha1: Option<String> = self
+            .db
+            .query_row(
+                "SELECT sha1 FROM checksums WHERE path = ?1 AND size = ?2 AND mtime_ns = ?3",
+                params![path.to_string_lossy(), size, mtime_ns],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(sha1.map(|sha1| Content { sha1, size }))
+    }
+
+    fn store(&mut self, path: &Path, size: u64, mtime_ns: i64, sha1: String) -> Result<Content> {
+        self.db.execute(
+            "INSERT OR REPLACE INTO checksums (path, size, mtime_ns, sha1) VALUES (?1, ?2, ?3, ?4)",
+            params![path.to_string_lossy(), size, mtime_ns, sha1],
+        )?;
+        Ok(Content { sha1, size })
+    }
+
+    /// The name `content` was uploaded under for `job`, if it was. Entries from older
+    /// versions lack the job, the size or even the hash; they match on what they have
+    /// and get completed here.
+    pub fn find(&mut self, job: &str, name: &str, content: &Content) -> Result<Option<String>> {
+        let found: Option<(i64, String, Option<u64>, String)> = self
+            .db
+            .query_row(
+                "SELECT id, name, size, job FROM uploads
+                 WHERE job IN (?3, '') AND sha1 = ?1 AND (size = ?2 OR size IS NULL)
+                 ORDER BY job = '', size IS NULL LIMIT 1",
+                params![content.sha1, content.size, job],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .optional()?;
+        if let Some((id, name, size, found_job)) = found {
+            if size.is_none() || found_job.is_empty() {
+                self.db.execute("UPDATE OR IGNORE uploads SET size = ?1, job = ?2 WHERE id = ?3", params![content.size, job, id])?;
+            }
+            return Ok(Some(name));
+        }
+        let changed = self.db.execute(
+            "UPDATE OR IGNORE uploads SET sha1 = ?1, size = ?2, job = ?3 WHERE name = ?4 AND sha1 IS NULL AND job IN (?3, '')",
+            params![content.sha1, content.size, job, name],
+        )?;
+        Ok((changed > 0).then(|| name.to_string()))
+    }
+
+    pub fn names(&self) -> Result<Vec<String>> {
+        let mut query = self.db.prepare("SELECT name FROM uploads")?;
+        let names = query.query_map([], |row| row.get(0))?.collect::<rusqlite::Result<_>>()?;
+        Ok(names)
+    }
+
+    /// `job`'s entries whose content hash is known, as (name, SHA-1).
+    pub fn hashed(&self, job: &str) -> Result<Vec<(String, String)>> {
+        let mut query = self.db.prepare("SELECT name, sha1 FROM uploads WHERE job = ?1 AND sha1 IS NOT NULL")?;
+        let entries = query.query_map([job], |row| Ok((row.get(0)?, row.get(1)?)))?.collect::<rusqlite::Result<_>>()?;
+        Ok(entries)
+    }
+
+    /// The albums `job`'s entries with this SHA-1 were added to.
+    pub fn albums(&self, job: &str, sha1: &str) -> Result<Vec<String>> {
+        let mut query = self.db.prepare("SELECT album_id FROM uploads WHERE job = ?1 AND sha1 = ?2 AND album_id IS NOT NULL")?;
+        let mut albums: Vec<String> = Vec::new();
+        for ids in query.query_map([job, sha1], |row| row.get::<_, String>(0))? {
+            for id in ids?.split(',').filter(|id| !id.is_empty()) {
+                if !albums.iter().any(|a| a == id) {
+                    albums.push(id.to_string());
+                }
+            }
+        }
+        Ok(albums)
+    }
+
+    /// Every entry, oldest upload first.
+    pub fn records(&self) -> Result<Vec<Record>> {
+        let mut query = self.db.prepare(&format!("SELECT {} FROM uploads ORDER BY recorded_at, id", RECORD_COLUMNS))?;
+        let rows = query.query_map([], record)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// The latest upload for `job` from this path (relative, as in `Record::path`).
+    /// Entries from before paths were kept match by filename.
+    pub fn at_path(&self, job: &str, path: &str, name: &str) -> Result<Option<Record>> {
+        let sql = format!(
+            "SELECT {} FROM uploads
+             WHERE job IN (?3, '') AND sha1 IS NOT NULL AND (path = ?1 OR (path IS NULL AND name = ?2))
+             ORDER BY recorded_at DESC LIMIT 1",
+            RECORD_COLUMNS
+        );
+        Ok(self.db.query_row(&sql, [path, name, job], record).optional()?)
+    }
+
+    /// Records uploaded files, all or none of them. Content already in the history only
+    /// has where it is now updated (and the asset and album, if they weren't known).
+    pub fn insert(&mut self, records: &[Record]) -> Result<()> {
+        let tx = self.db.transaction()?;
+        for record in records {
+            // NULLs never conflict, so entries from before sizes (or hashes) are replaced here
+            let job = &record.job;
+            match &record.sha1 {
+                Some(sha1) => tx.execute("DELETE FROM uploads WHERE job IN (?2, '') AND sha1 = ?1 AND size IS NULL", [sha1, job])?,
+                None => tx.execute("DELETE FROM uploads WHERE job IN (?2, '') AND name = ?1 AND sha1 IS NULL", [&record.name, job])?,
+            };
+            tx.execute(
+                "INSERT INTO uploads (job, sha1, size, name, path, mtime, asset_id, album_id, recorded_at)
+                 VALUES (?9, ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                 ON CONFLICT (job, sha1, size) DO UPDATE SET
+                    name = excluded.name,
+                    path = excluded.path,
+                    mtime = excluded.mtime,
+                    asset_id = coalesce(excluded.asset_id, asset_id),
+                    album_id = coalesce(excluded.album_id, album_id)",
+                params![
+                    record.sha1,
+                    record.size,
+                    record.name,
+                    record.path,
+                    record.mtime,
+                    record.asset_id,
+                    record.album_id,
+                    record.uploaded_at.to_rfc3339(),
+                    job
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Entries not yet tied to a server asset, as (job, name, SHA-1 if known).
+    pub fn unresolved(&self) -> Result<Vec<(String, String, Option<String>)>> {
+        let mut query = self.db.prepare("SELECT job, name, sha1 FROM uploads WHERE asset_id IS NULL")?;
+        let entries = query.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?.collect::<rusqlite::Result<_>>()?;
+        Ok(entries)
+    }
+
+    /// Ties `job`'s entry with this hash (or, without one, this name) to a server asset.
+    pub fn set_asset(&mut self, job: &str, name: &str, sha1: Option<&str>, asset_id: &str) -> Result<()> {
+        match sha1 {
+            Some(sha1) => self.db.execute("UPDATE uploads SET asset_id = ?1 WHERE job = ?3 AND sha1 = ?2", [asset_id, sha1, job])?,
+            None => self.db.execute(
+                "UPDATE uploads SET asset_id = ?1 WHERE job = ?3 AND name = ?2 AND sha1 IS NULL",
+                [asset_id, name, job],
+            )?,
+        };
+        Ok(())
+    }
+
+    /// Notes an album these assets were added to, next to any noted before.
+    pub fn set_album(&mut self, asset_ids: &[String], album_id: &str) -> Result<()> {
+        let tx = self.db.transaction()?;
+        for asset_id in asset_ids {
+            tx.execute(
+                "UPDATE uploads SET album_id = CASE
+                    WHEN album_id IS NULL OR album_id = '' THEN ?1
+                    WHEN instr(',' || album_id || ',', ',' || ?1 || ',') > 0 THEN album_id
+                    ELSE album_id || ',' || ?1
+                 END WHERE asset_id = ?2",
+                [album_id, asset_id],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Forgets these entries (as returned by `records`): by content where they have it,
+    /// else by name.
+    pub fn forget(&mut self, records: &[Record]) -> Result<()> {
+        let tx = self.db.transaction()?;
+        for record in records {
+            match &record.sha1 {
+                Some(sha1) => {
+                    tx.execute("DELETE FROM uploads WHERE job = ?3 AND sha1 = ?1 AND size IS ?2", params![sha1, record.size, record.job])?;
+                    forget_checksum(&tx, &record.job, record.path.as_deref(), sha1)?;
+                }
+                None => {
+                    tx.execute("DELETE FROM uploads WHERE job = ?2 AND name = ?1 AND sha1 IS NULL", [&record.name, &record.job])?;
+                }
+            };
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Forgets `job`'s entries with these SHA-1s; other jobs keep theirs.
+    pub fn remove(&mut self, job: &str, hashes: &[String]) -> Result<()> {
+        let tx = self.db.transaction()?;
+        for hash in hashes {
+            let paths: Vec<Option<String>> = tx
+                .prepare("SELECT path FROM uploads WHERE job = ?2 AND sha1 = ?1")?
+                .query_map([hash, job], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()?;
+            for path in paths {
+                forget_checksum(&tx, job, path.as_deref(), hash)?;
+            }
+            tx.execute("DELETE FROM uploads WHERE job = ?2 AND sha1 = ?1", [hash, job])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+/// Size and mtime (ns) of a file, which tell whether its cached hash still holds.
+fn stamp(path: &Path) -> Result<(u64, i64)> {
+    let metadata = fs::metadata(path)?;
+    let mtime_ns = metadata.modified()?.duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as i64).unwrap_or(0);
+    Ok((metadata.len(), mtime_ns))
+}
+
+/// Drops the cached hash of the file an upload entry was for, unless the file has
+/// changed since (then the cached hash is the new content's).
+fn forget_checksum(db: &Connection, job: &str, path: Option<&str>, sha1: &str) -> Result<()> {
+    if let Some(path) = path {
+        let local = key_path(&format!("{}|{}", job, path));
+        db.execute("DELETE FROM checksums WHERE path = ?1 AND sha1 = ?2", params![local.to_string_lossy(), sha1])?;
+    }
+    Ok(())
+}
+
+#[instrument(name = "hash", skip_all, fields(file = %path.display()))]
+pub fn hash_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha1::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+/// `hash_file` for bytes already in memory.
+pub fn hash_bytes(data: &[u8]) -> String {
+    hex(&Sha1::digest(data))
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty() -> History {
+        let mut history = History { db: Connection::open_in_memory().unwrap() };
+        history.migrate().unwrap();
+        history
+    }
+
+    fn content(sha1: &str) -> Content {
+        Content { sha1: sha1.to_string(), size: 10 }
+    }
+
+    fn record(job: &str, name: &str, sha1: &str) -> Record {
+        Record {
+            job: job.to_string(),
+            name: name.to_string(),
+            path: Some(name.to_string()),
+            sha1: Some(sha1.to_string()),
+            size: Some(10),
+            mtime: None,
+            uploaded_at: Utc::now(),
+            asset_id: Some(format!("asset-{}", sha1)),
+            album_id: None,
+        }
+    }
+
+    #[test]
+    fn migrates_a_v1_database() {
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(
+            "CREATE TABLE uploads (name TEXT PRIMARY KEY, sha1 TEXT, recorded_at TEXT NOT NULL);
+            INSERT INTO uploads VALUES ('a.jpg', 'aaaa', '2024-01-01T00:00:00+00:00');
+            INSERT INTO uploads VALUES ('old.jpg', NULL, '2024-01-02T00:00:00+00:00');
+            PRAGMA user_version = 1;",
+        )
+        .unwrap();
+        let mut history = History { db };
+        history.migrate().unwrap();
+
+        let version: i32 = history.db.pragma_query_value(None, "user_version", |row| row.get(0)).unwrap();
+        assert_eq!(version, SCHEMA_VERSION);
+        let records = history.records().unwrap();
+        let names: Vec<&str> = records.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["a.jpg", "old.jpg"]);
+        assert!(records.iter().all(|r| r.job.is_empty() && r.size.is_none() && r.asset_id.is_none()));
+
+        // The first job to find a legacy entry claims it, by hash or else by name
+        assert_eq!(history.find("h|/a", "renamed.jpg", &content("aaaa")).unwrap().as_deref(), Some("a.jpg"));
+        assert_eq!(history.find("h|/b", "a.jpg", &content("aaaa")).unwrap(), None);
+        assert_eq!(history.find("h|/a", "old.jpg", &content("bbbb")).unwrap().as_deref(), Some("old.jpg"));
+        assert_eq!(history.hashed("h|/a").unwrap().len(), 2);
+        assert!(history.hashed("").unwrap().is_empty());
+        let checksums: i64 = history.db.query_row("SELECT count(*) FROM checksums", [], |row| row.get(0)).unwrap();
+        assert_eq!(checksums, 0);
+    }
+
+    #[test]
+    fn keeps_jobs_apart() {
+        let mut history = empty();
+        history.insert(&[record("h|/a", "x.jpg", "aaaa"), record("h|/b", "x.jpg", "aaaa")]).unwrap();
+        assert_eq!(history.find("h|/a", "x.jpg", &content("aaaa")).unwrap().as_deref(), Some("x.jpg"));
+        assert_eq!(history.find("h|/b", "x.jpg", &content("aaaa")).unwrap().as_deref(), Some("x.jpg"));
+        assert_eq!(history.find("h|/c", "x.jpg", &content("aaaa")).unwrap(), None);
+
+        history.forget(&[record("h|/a", "x.jpg", "aaaa")]).unwrap();
+        assert_eq!(history.find("h|/a", "x.jpg", &content("aaaa")).unwrap(), None);
+        assert_eq!(history.find("h|/b", "x.jpg", &content("aaaa")).unwrap().as_deref(), Some("x.jpg"));
+
+        history.insert(&[record("h|/a", "y.jpg", "cccc")]).unwrap();
+        history.remove("h|/b", &["cccc".to_string(), "aaaa".to_string()]).unwrap();
+        assert!(history.hashed("h|/b").unwrap().is_empty());
+        assert_eq!(history.hashed("h|/a").unwrap(), [("y.jpg".to_string(), "cccc".to_string())]);
+    }
+
+    #[test]
+    fn prunes_checksums_with_their_entries() {
+        let mut history = empty();
+        history.insert(&[record("h|/a", "x.jpg", "aaaa"), record("h|/a", "y.jpg", "cccc")]).unwrap();
+        history
+            .db
+            .execute_batch(
+                "INSERT INTO checksums VALUES ('/a/x.jpg', 10, 0, 'aaaa'), ('/a/y.jpg', 10, 0, 'cccc'), ('/a/z.jpg', 10, 0, 'dddd');",
+            )
+            .unwrap();
+        let cached = |history: &History| -> Vec<String> {
+            let mut statement = history.db.prepare("SELECT path FROM checksums ORDER BY path").unwrap();
+            statement.query_map([], |row| row.get(0)).unwrap().map(|p| p.unwrap()).collect()
+        };
+
+        history.forget(&[record("h|/a", "x.jpg", "aaaa")]).unwrap();
+        assert_eq!(cached(&history), ["/a/y.jpg", "/a/z.jpg"]);
+        history.remove("h|/a", &["cccc".to_string()]).unwrap();
+        assert_eq!(cached(&history), ["/a/z.jpg"]);
+
+        // An edited file's hash is of its new content, which stays cached
+        history.db.execute("UPDATE checksums SET path = '/a/x.jpg'", []).unwrap();
+        history.insert(&[record("h|/a", "x.jpg", "aaaa")]).unwrap();
+        history.forget(&[record("h|/a", "x.jpg", "aaaa")]).unwrap();
+        assert_eq!(cached(&history), ["/a/x.jpg"]);
+    }
+}
@@ -0,0 +1,77 @@
+use log::kv::{Error, Key, Value, VisitSource};
+use log::{LevelFilter, Log, Metadata, Record};
+use serde_json::{Map, json};
+use simplelog::SharedLogger;
+use std::io::Write;
+use std::sync::Mutex;
+
+/// `--log-format json`: one JSON object per line with the time, level and message,
+/// plus whatever key-values the event carries (`file`, `action`, `status`, `bytes`,
+/// `duration_ms`, `error`), for Loki, Elasticsearch and the like.
+pub struct JsonLogger {
+    level: LevelFilter,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonLogger {
+    pub fn new(level: LevelFilter, out: impl Write + Send + 'static) -> Box<Self> {
+        Box::new(Self { level, out: Mutex::new(Box::new(out)) })
+    }
+}
+
+struct Fields<'a>(&'a mut Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+        let value = if let Some(n) = value.to_u64() {
+            json!(n)
+        } else if let Some(n) = value.to_i64() {
+            json!(n)
+        } else if let Some(n) = value.to_f64() {
+            json!(n)
+        } else if let Some(b) = value.to_bool() {
+            json!(b)
+        } else {
+            json!(value.to_string())
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut event = Map::new();
+        event.insert("time".into(), json!(chrono::Utc::now().to_rfc3339()));
+        event.insert("level".into(), json!(record.level().as_str()));
+        event.insert("message".into(), json!(record.args().to_string().trim()));
+        let _ = record.key_values().visit(&mut Fields(&mut event));
+        let mut out = self.out.lock().unwrap();
+        let _ = writeln!(out, "{}", serde_json::Value::Object(event));
+    }
+
+    fn flush(&self) {
+        let _ = self.out.lock().unwrap().flush();
+    }
+}
+
+impl SharedLogger for JsonLogger {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&simplelog::Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        self
+    }
+}
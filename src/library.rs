@@ -0,0 +1,56 @@
+use crate::api::{
+    create_library, get_active_url, get_my_user_id, list_libraries, scan_library, set_library_paths, validate_key,
+    validate_library_paths,
+};
+use crate::config::{Config, ExternalLibrary};
+use anyhow::{Context, Result, bail};
+use log::{info, warn};
+use reqwest::Client;
+
+/// External library mode: makes sure the library exists and covers every synced folder,
+/// then has the server rescan it. Nothing is uploaded; the server reads the files itself.
+pub async fn refresh(client: &Client, config: &Config, library: &ExternalLibrary) -> Result<()> {
+    let Some(base_url) = &get_active_url(client, &config.local_url, &config.ext_url).await else {
+        bail!("Could not connect to any Immich instance.");
+    };
+    let key = &config.api_key;
+    validate_key(client, base_url, key).await?;
+    let wanted: Vec<String> = config.folders.iter().map(|f| library.server_path(&f.path)).collect();
+
+    let existing = list_libraries(client, base_url, key)
+        .await
+        .context("Could not list external libraries (this needs an admin API key)")?
+        .into_iter()
+        .find(|l| l.name == library.name);
+    let (id, import_paths) = match existing {
+        Some(found) => {
+            let mut paths = found.import_paths;
+            let missing: Vec<String> = wanted.iter().filter(|p| !paths.contains(p)).cloned().collect();
+            if !missing.is_empty() {
+                info!("Adding {} to external library '{}'", missing.join(", "), library.name);
+                paths.extend(missing);
+                set_library_paths(client, base_url, key, &found.id, &paths).await?;
+            }
+            (found.id, paths)
+        }
+        None => {
+            info!("Creating external library '{}' for {}", library.name, wanted.join(", "));
+            let owner = get_my_user_id(client, base_url, key).await?;
+            let created = create_library(client, base_url, key, &owner, &library.name, &wanted).await?;
+            (created.id, wanted)
+        }
+    };
+
+    for check in validate_library_paths(client, base_url, key, &id, &import_paths).await? {
+        if !check.is_valid {
+            warn!(
+                "The server can't read {}: {} (set IMMICH_LIBRARY_PATH_MAP if it mounts the folder elsewhere)",
+                check.import_path,
+                check.message.unwrap_or_default()
+            );
+        }
+    }
+    scan_library(client, base_url, key, &id).await?;
+    info!("Asked the server to rescan external library '{}'.", library.name);
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// When the log file is rotated, and how many old ones are kept.
+pub struct Rotation {
+    pub max_bytes: Option<u64>,
+    pub max_age: Option<Duration>,
+    pub keep: usize,
+}
+
+/// The log file, appended to across runs and rotated once it grows past the size
+/// limit or gets older than the age limit: `immich_backup.log` becomes `.log.1`, `.1`
+/// becomes `.2` and so on, the oldest beyond `keep` being deleted.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    started: SystemTime,
+    rotation: Rotation,
+}
+
+impl RotatingFile {
+    pub fn open(path: PathBuf, rotation: Rotation) -> io::Result<Self> {
+        let (file, size, started) = open_append(&path)?;
+        Ok(Self { path, file, size, started, rotation })
+    }
+
+    fn is_due(&self) -> bool {
+        let too_big = self.rotation.max_bytes.is_some_and(|max| self.size >= max);
+        let too_old = self.rotation.max_age.is_some_and(|max| self.started.elapsed().is_ok_and(|age| age >= max));
+        self.size > 0 && (too_big || too_old)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        match self.rotation.keep {
+            0 => fs::remove_file(&self.path)?,
+            keep => {
+                let _ = fs::remove_file(numbered(&self.path, keep));
+                for n in (1..keep).rev() {
+                    let _ = fs::rename(numbered(&self.path, n), numbered(&self.path, n + 1));
+                }
+                fs::rename(&self.path, numbered(&self.path, 1))?;
+            }
+        }
+        (self.file, self.size, self.started) = open_append(&self.path)?;
+        Ok(())
+    }
+}
+
+/// The file, its size and when it was started.
+fn open_append(path: &Path) -> io::Result<(File, u64, SystemTime)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let metadata = file.metadata()?;
+    let started = metadata.created().unwrap_or_else(|_| SystemTime::now());
+    Ok((file, metadata.len(), started))
+}
+
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{}", n));
+    name.into()
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A failed rotation shouldn't cost the log line; it's retried on the next one
+        if self.is_due() {
+            let _ = self.rotate();
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
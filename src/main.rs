@@ -1,298 +1,504 @@
+mod album_cache;
+mod albums;
+mod api;
+mod archive;
+mod burst;
+mod catchup;
+mod client;
+mod compat;
+mod config;
+mod connections;
+mod control;
+mod daemon;
+mod dead_letter;
+mod diff;
+mod email;
+mod error;
+mod gpx;
+mod handler;
+mod health;
+mod healthcheck;
+mod history;
+mod json_log;
+mod library;
+mod log_file;
+mod mappings;
+mod metadata;
+mod metrics;
+mod migrate;
+mod network;
+mod notify;
+mod ownership;
+mod passthrough;
+mod post_upload;
+mod progress;
+mod quota;
+mod rate_budget;
+mod receipts;
+mod reconcile;
+mod report;
+mod rules;
+mod run;
+mod scan;
+mod schedule;
+mod session;
+mod shared_link;
+mod skips;
+mod stats;
+mod state;
+mod status;
+mod summary;
+mod sync;
+mod telemetry;
+mod transform;
+mod trash;
+mod trigger;
+
 use anyhow::Result;
-use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+use config::{Config, UploadOrder};
 use dotenvy::dotenv;
-use log::{error, info, warn};
-use reqwest::{Client, StatusCode};
-use serde::Deserialize;
+use indicatif_log_bridge::LogWrapper;
+use json_log::JsonLogger;
+use log_file::{RotatingFile, Rotation};
+use history::{History, Record};
+use log::info;
+use reqwest::Client;
 use simplelog::*;
+use status::Status;
 use std::collections::HashSet;
-use std::env;
-use std::fs::{self, File};
-use std::path::{Path, PathBuf};
+use std::fs::File;
+use std::io::IsTerminal;
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
-use tokio::sync::Semaphore;
-use tokio::task::JoinSet;
+use std::time::Duration;
 
-// --- CONFIGURATION STRUCTS ---
-#[derive(Deserialize)]
-struct Album {
-    id: String,
-    #[serde(rename = "albumName")]
-    album_name: String,
-}
+const LOG_FILE: &str = "immich_backup.log";
 
-#[derive(Deserialize)]
-struct AssetResponse {
-    id: String,
+#[derive(Parser)]
+#[command(about = "Uploads new screenshots to an Immich album")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Keep running and sync periodically (send SIGQUIT to dump status)
+    #[arg(long)]
+    daemon: bool,
+
+    /// Watch the folder and upload new files as soon as they appear
+    #[arg(long)]
+    watch: bool,
+
+    /// Seconds between sync passes in daemon mode (and batch scans in watch mode)
+    #[arg(long, env = "IMMICH_SYNC_INTERVAL", default_value_t = 300)]
+    interval: u64,
+
+    /// Order in which pending files are uploaded
+    #[arg(long, value_enum, env = "IMMICH_UPLOAD_ORDER", default_value_t = UploadOrder::Scan)]
+    order: UploadOrder,
+
+    /// Pending file events before watch mode falls back to batch scans
+    #[arg(long, env = "IMMICH_WATCH_BACKLOG", default_value_t = 200)]
+    watch_backlog: usize,
+
+    /// Plain console output for screen readers and log pipelines: no colour, padding
+    /// or progress bars, everything on stdout, one line per event
+    #[arg(long, env = "IMMICH_PLAIN")]
+    plain: bool,
+
+    /// Format of the console and log file output
+    #[arg(long, value_enum, env = "IMMICH_LOG_FORMAT", default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Rotate the log file once it reaches this many MB (0 for no size limit)
+    #[arg(long, env = "IMMICH_LOG_MAX_MB", default_value_t = 10)]
+    log_max_mb: u64,
+
+    /// Rotate the log file once it is this many days old (0 for no age limit)
+    #[arg(long, env = "IMMICH_LOG_MAX_DAYS", default_value_t = 0)]
+    log_max_days: u64,
+
+    /// Rotated log files to keep
+    #[arg(long, env = "IMMICH_LOG_KEEP", default_value_t = 5)]
+    log_keep: usize,
+
+    /// Export traces of the sync pipeline to this OTLP/HTTP collector, e.g.
+    /// `http://localhost:4318` for Jaeger or Tempo
+    #[arg(long, env = "IMMICH_OTLP_ENDPOINT")]
+    otlp_endpoint: Option<String>,
 }
 
-const HISTORY_FILE: &str = "immich_upload_history.json";
-const LOG_FILE: &str = "immich_backup.log";
-const DEVICE_ID: &str = "rust-uploader-v1";
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum LogFormat {
+    Text,
+    /// One JSON object per event, for log shippers
+    Json,
+}
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // 1. Setup Logging (Console + File)
-    CombinedLogger::init(vec![
-        TermLogger::new(
-            LevelFilter::Info,
-            Config::default(),
-            TerminalMode::Mixed,
-            ColorChoice::Auto,
-        ),
-        WriteLogger::new(
-            LevelFilter::Info,
-            Config::default(),
-            File::create(LOG_FILE).expect("Failed to create log file"),
-        ),
-    ])?;
+#[derive(Subcommand)]
+enum Command {
+    /// Send a command to a running daemon: pause, resume or sync-now
+    Control {
+        #[arg(value_parser = control::COMMANDS)]
+        action: String,
+    },
+    /// Compare the local folder, the upload history and the server album
+    Diff {
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+        /// Include files that are present everywhere
+        #[arg(long)]
+        all: bool,
+    },
+    /// Add uploaded assets that never made it into the album
+    Reconcile {
+        /// Only report what would be added
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Move an older upload history into the state database and tie its entries to
+    /// server assets
+    Migrate,
+    /// Sign in (OAuth, or email and password) and save a session to use instead of an API key
+    Login {
+        /// Log in with this email and a password read from stdin instead of OAuth
+        #[arg(long)]
+        email: Option<String>,
+    },
+    /// Send a raw authenticated request to the Immich API, e.g. `api GET /albums`
+    Api {
+        method: String,
+        /// Path below /api
+        path: String,
+        /// JSON request body
+        #[arg(long)]
+        data: Option<String>,
+    },
+    /// Inspect, prune or edit the upload history
+    History {
+        #[command(subcommand)]
+        action: HistoryAction,
+    },
+    /// Inspect or reset files that repeatedly failed to upload
+    DeadLetter {
+        #[command(subcommand)]
+        action: DeadLetterAction,
+    },
+    /// Audit why files were not uploaded
+    Skips {
+        #[command(subcommand)]
+        action: SkipsAction,
+    },
+    /// Print totals from the state store: files tracked, uploads per day and week,
+    /// failures and the largest pending files
+    Stats {
+        /// How many of the largest pending files to list
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+    },
+    /// Review, restore or permanently delete assets in the server's trash
+    Trash {
+        #[command(subcommand)]
+        action: TrashAction,
+    },
+}
 
-    // 2. Load .env
-    dotenv().ok();
-    let folder = env::var("SCREENSHOTS_PATH").expect("SCREENSHOTS_PATH not set");
-    let api_key = env::var("IMMICH_API_KEY").expect("IMMICH_API_KEY not set");
-    let local_url = env::var("IMMICH_LOCAL_URL").unwrap_or_default();
-    let ext_url = env::var("IMMICH_EXTERNAL_URL").unwrap_or_default();
-    let album_name = env::var("IMMICH_ALBUM_NAME").expect("IMMICH_ALBUM_NAME not set");
+#[derive(Subcommand)]
+enum TrashAction {
+    /// Show trashed assets uploaded by this tool
+    List {
+        /// Every trashed asset, whoever uploaded it
+        #[arg(long)]
+        all: bool,
+    },
+    /// Restore the given asset IDs, or every asset `trash list` shows
+    Restore {
+        ids: Vec<String>,
+        /// Everything in the trash
+        #[arg(long, conflicts_with = "ids")]
+        all: bool,
+    },
+    /// Permanently delete the assets `trash list` shows
+    Empty {
+        /// Everything in the trash
+        #[arg(long)]
+        all: bool,
+        /// Don't ask for confirmation
+        #[arg(long)]
+        yes: bool,
+    },
+}
 
-    let client = Client::builder().timeout(Duration::from_secs(60)).build()?;
+#[derive(Subcommand)]
+enum HistoryAction {
+    /// Show recorded uploads, optionally only names or paths containing `filter`
+    List { filter: Option<String> },
+    /// Forget entries whose file is no longer in any synced folder
+    Prune {
+        /// Only list what would be forgotten
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Forget entries whose name or path matches a `*`/`?` pattern, so the next sync
+    /// considers those files again
+    Forget { pattern: String },
+    /// Print the history as JSON
+    Export,
+    /// Add entries from a `history export` file (`-` for stdin)
+    Import { file: PathBuf },
+}
 
-    // 3. Network Detection
-    let base_url = match get_active_url(&client, &local_url, &ext_url).await {
-        Some(url) => url,
-        None => {
-            error!("Could not connect to any Immich instance.");
-            return Ok(());
-        }
-    };
+#[derive(Subcommand)]
+enum SkipsAction {
+    /// Show the last skip decision per file, optionally only for paths containing `filter`
+    Show { filter: Option<String> },
+}
 
-    // 4. Find Album ID
-    info!("Looking for album: '{}'...", album_name);
-    let album_id = match get_album_id(&client, &base_url, &api_key, &album_name).await {
-        Ok(Some(id)) => id,
-        Ok(None) => {
-            error!("Album '{}' not found on server!", album_name);
-            return Ok(());
-        }
-        Err(e) => {
-            error!("Error fetching albums: {:?}", e);
-            return Ok(());
-        }
-    };
+#[derive(Subcommand)]
+enum DeadLetterAction {
+    /// Show files with failed uploads and their attempt counts
+    List,
+    /// Make a file (or all files) eligible for upload again: by its path, its path
+    /// under the synced folder, or its name for every file called that
+    Reset { name: Option<String> },
+}
 
-    // 5. Load History
-    let mut history = load_history()?;
-    let path = Path::new(&folder);
-    if !path.exists() {
-        error!("Screenshots folder not found: {}", folder);
-        return Ok(());
+#[tokio::main]
+async fn main() -> Result<()> {
+    // 1. Load .env (before parsing, so options can come from it); the working directory's
+    // wins over the one in the config folder
+    dotenv().ok();
+    if let Some(dir) = state::config_dir() {
+        dotenvy::from_path(dir.join(".env")).ok();
     }
+    let cli = Cli::parse();
+    let moved = state::init()?;
 
-    // 6. Process Files
-    let mut entries: Vec<PathBuf> = fs::read_dir(path)?
-        .filter_map(|e| e.ok())
-        .map(|e| e.path())
-        .filter(|p| {
-            if let Some(ext) = p.extension() {
-                let s = ext.to_string_lossy().to_lowercase();
-                matches!(s.as_str(), "png" | "jpg" | "jpeg" | "webp")
-            } else {
-                false
-            }
-        })
-        .collect();
-
-    // Sort by modification time (Oldest first)
-    entries.sort_by_key(|p| p.metadata().ok().and_then(|m| m.modified().ok()).unwrap_or(SystemTime::UNIX_EPOCH));
-
-    let client_arc = client.clone();
-    let base_url_arc = Arc::new(base_url);
-    let api_key_arc = Arc::new(api_key);
-    
-    // Concurrency control: max 5 parallel uploads
-    let semaphore = Arc::new(Semaphore::new(5));
-    let mut join_set = JoinSet::new();
-
-    for file_path in entries {
-        let filename = file_path.file_name().unwrap().to_string_lossy().to_string();
-
-        if history.contains(&filename) {
-            continue;
+    match &cli.command {
+        Some(Command::Control { action }) => {
+            println!("{}", control::send(action).await?);
+            return Ok(());
         }
-
-        let permit = semaphore.clone().acquire_owned().await.unwrap();
-        let client_c = client_arc.clone();
-        let base_url_c = base_url_arc.clone();
-        let api_key_c = api_key_arc.clone();
-        let file_path_c = file_path.clone();
-
-        join_set.spawn(async move {
-            info!("Uploading: {}...", filename);
-            let result = upload_asset(&client_c, &file_path_c, &base_url_c, &api_key_c).await;
-            drop(permit);
-            (filename, result)
-        });
-    }
-
-    let mut successful_asset_ids = Vec::new();
-    let mut uploaded_count = 0;
-
-    while let Some(res) = join_set.join_next().await {
-        match res {
-            Ok((filename, Ok(Some(asset_id)))) => {
-                if asset_id != "DUPLICATE_UNKNOWN_ID" {
-                    successful_asset_ids.push(asset_id);
+        Some(Command::DeadLetter { action }) => return dead_letter_command(action),
+        Some(Command::History { action }) => return history_command(action).await,
+        Some(Command::Skips { action: SkipsAction::Show { filter } }) => {
+            for (key, skip) in skips::SkipLog::load().iter() {
+                let path = history::key_path(key);
+                if filter.as_ref().is_none_or(|f| path.to_string_lossy().contains(f.as_str())) {
+                    println!(
+                        "{}\t{}\tlast {}\t{} time(s)",
+                        path.display(),
+                        skip.reason,
+                        skip.last_seen.to_rfc3339(),
+                        skip.count
+                    );
                 }
-                history.insert(filename);
-                uploaded_count += 1;
             }
-            Ok((_, Ok(None))) => { /* Failed, do nothing */ }
-            Ok((filename, Err(e))) => error!("Upload error for {}: {:?}", filename, e),
-            Err(e) => error!("Task join error: {:?}", e),
+            return Ok(());
         }
-    }
-
-    if uploaded_count > 0 {
-        if let Err(e) = save_history(&history) {
-            error!("Failed to save history: {:?}", e);
+        Some(Command::Diff { json, all }) => {
+            let config = Config::from_env()?;
+            return diff::run(&build_client(&config)?, &config, *json, *all).await;
         }
-    }
-
-    if !successful_asset_ids.is_empty() {
-        info!("Adding {} assets to album in batches...", successful_asset_ids.len());
-        for chunk in successful_asset_ids.chunks(50) {
-            if let Err(e) = add_to_album(&client, &base_url_arc, &api_key_arc, &album_id, chunk).await {
-                error!("Failed to link to album batch: {:?}", e);
-            }
+        Some(Command::Api { method, path, data }) => {
+            let config = Config::from_env()?;
+            return passthrough::run(&build_client(&config)?, &config, method, path, data.as_deref()).await;
+        }
+        Some(Command::Login { email }) => return login_command(email.as_deref()).await,
+        Some(Command::Stats { top }) => return stats::run(&Config::from_env()?, *top).await,
+        Some(Command::Trash { action }) => {
+            let config = Config::from_env()?;
+            let client = build_client(&config)?;
+            return match action {
+                TrashAction::List { all } => trash::list(&client, &config, *all).await,
+                TrashAction::Restore { ids, all } => trash::restore(&client, &config, ids, *all).await,
+                TrashAction::Empty { all, yes } => trash::empty(&client, &config, *all, *yes).await,
+            };
         }
+        Some(Command::Reconcile { .. } | Command::Migrate) | None => {}
     }
 
-    if uploaded_count > 0 {
-        info!("Done! Processed {} images.", uploaded_count);
+    // 2. Setup Logging (Console + File)
+    let console = if cli.plain {
+        TermLogger::new(
+            LevelFilter::Info,
+            ConfigBuilder::new()
+                .set_level_padding(LevelPadding::Off)
+                .set_thread_level(LevelFilter::Off)
+                .set_target_level(LevelFilter::Off)
+                .build(),
+            TerminalMode::Stdout,
+            ColorChoice::Never,
+        )
+    } else {
+        TermLogger::new(
+            LevelFilter::Info,
+            simplelog::Config::default(),
+            TerminalMode::Mixed,
+            ColorChoice::Auto,
+        )
+    };
+    let rotation = Rotation {
+        max_bytes: (cli.log_max_mb > 0).then(|| cli.log_max_mb * 1024 * 1024),
+        max_age: (cli.log_max_days > 0).then(|| Duration::from_secs(cli.log_max_days * 24 * 60 * 60)),
+        keep: cli.log_keep,
+    };
+    let log_file = RotatingFile::open(log_path(), rotation).expect("Failed to open log file");
+    let loggers: Vec<Box<dyn SharedLogger>> = match cli.log_format {
+        LogFormat::Text => vec![console, WriteLogger::new(LevelFilter::Info, simplelog::Config::default(), log_file)],
+        LogFormat::Json => vec![
+            JsonLogger::new(LevelFilter::Info, std::io::stdout()),
+            JsonLogger::new(LevelFilter::Info, log_file),
+        ],
+    };
+    // Progress bars only for someone watching; cron and pipes get the plain log lines
+    if !cli.plain && cli.log_format == LogFormat::Text && std::io::stderr().is_terminal() {
+        LogWrapper::new(progress::enable(), CombinedLogger::new(loggers)).try_init()?;
     } else {
-        info!("No new screenshots found.");
+        CombinedLogger::init(loggers)?;
     }
-
-    Ok(())
-}
-
-// --- HELPER FUNCTIONS ---
-
-async fn get_active_url(client: &Client, local: &str, external: &str) -> Option<String> {
-    if !local.is_empty() {
-        info!("Checking connection to: {}...", local);
-        // Updated API endpoint
-        if client.get(format!("{}/api/server/ping", local)).timeout(Duration::from_secs(2)).send().await.is_ok() {
-            info!("Local network detected.");
-            return Some(local.to_string());
-        }
+    if !moved.is_empty() {
+        info!("Moved {} from the working directory to {}", moved.join(", "), state::root().display());
     }
-    
-    if !external.is_empty() {
-        info!("Switching to External URL.");
-        return Some(external.to_string());
+    // Held until main returns, which flushes the last spans
+    let _telemetry = cli.otlp_endpoint.as_deref().map(telemetry::init).transpose()?;
+
+    let mut config = Config::from_env()?;
+    config.order = cli.order;
+    let client = build_client(&config)?;
+    if let Some(Command::Reconcile { dry_run }) = &cli.command {
+        return reconcile::run(&client, &config, *dry_run).await;
     }
-    None
-}
+    if let Some(Command::Migrate) = &cli.command {
+        return migrate::run(&client, &config).await;
+    }
+    let status = Arc::new(Status::default());
 
-async fn get_album_id(client: &Client, base_url: &str, key: &str, name: &str) -> Result<Option<String>> {
-    let url = format!("{}/api/albums", base_url);
-    let resp = client.get(&url).header("x-api-key", key).send().await?;
-    resp.error_for_status_ref()?;
-    
-    let albums: Vec<Album> = resp.json().await?;
-    for album in albums {
-        if album.album_name == name {
-            return Ok(Some(album.id));
+    let interval = Duration::from_secs(cli.interval);
+    if cli.watch {
+        daemon::watch(&client, &config, status, interval, cli.watch_backlog).await
+    } else if cli.daemon {
+        daemon::run(&client, &config, status, interval).await
+    } else {
+        if let Some(reason) = schedule::blocked_reason(&config).await {
+            info!("{}, nothing to do.", reason);
+            return Ok(());
         }
+        sync::run_sync(&client, &config, &status, None).await
     }
-    Ok(None)
 }
 
-async fn add_to_album(client: &Client, base_url: &str, key: &str, album_id: &str, asset_ids: &[String]) -> Result<()> {
-    let url = format!("{}/api/albums/{}/assets", base_url, album_id);
-    let body = serde_json::json!({ "ids": asset_ids });
-    
-    client.put(&url)
-        .header("x-api-key", key)
-        .json(&body)
-        .send()
-        .await?
-        .error_for_status()?;
-        
-    info!("   -- Added {} assets to album", asset_ids.len());
-    Ok(())
+/// `IMMICH_LOG_FILE`, else `LOG_FILE` in the state folder.
+fn log_path() -> PathBuf {
+    std::env::var_os("IMMICH_LOG_FILE").map_or_else(|| state::root().join(LOG_FILE), PathBuf::from)
 }
 
-async fn upload_asset(client: &Client, path: &Path, base_url: &str, key: &str) -> Result<Option<String>> {
-    let filename = path.file_name().unwrap().to_string_lossy();
-    let metadata = fs::metadata(path)?;
-    let size = metadata.len();
-    
-    // Create timestamps in strict ISO format for Immich
-    let created: DateTime<Utc> = metadata.created().unwrap_or(SystemTime::now()).into();
-    let modified: DateTime<Utc> = metadata.modified().unwrap_or(SystemTime::now()).into();
-    
-    let device_asset_id = format!("{}-{}-{}", filename, size, modified.timestamp());
-
-    // Prepare multipart form
-    let file_bytes = tokio::fs::read(path).await?;
-    let mime = mime_guess::from_path(path).first_or_octet_stream();
-    
-    let part = reqwest::multipart::Part::bytes(file_bytes)
-        .file_name(filename.to_string())
-        .mime_str(mime.as_ref())?;
-
-    let form = reqwest::multipart::Form::new()
-        .part("assetData", part)
-        .text("deviceAssetId", device_asset_id)
-        .text("deviceId", DEVICE_ID)
-        .text("fileCreatedAt", created.to_rfc3339())
-        .text("fileModifiedAt", modified.to_rfc3339())
-        .text("isFavorite", "false");
-
-    let resp = client.post(format!("{}/api/assets", base_url))
-        .header("x-api-key", key)
-        .multipart(form)
-        .send()
-        .await?;
-
-    let status = resp.status();
+// Runs before there's a config: that needs the API key (or the session made here)
+async fn login_command(email: Option<&str>) -> Result<()> {
+    let client = client::configure(Client::builder(), &config::ConnectionSettings::from_env()?)?.build()?;
+    let local = std::env::var("IMMICH_LOCAL_URL").unwrap_or_default();
+    let external = std::env::var("IMMICH_EXTERNAL_URL").unwrap_or_default();
+    let Some(base_url) = api::get_active_url(&client, &local, &external).await else {
+        anyhow::bail!("Could not connect to any Immich instance.");
+    };
+    session::login(&client, &base_url, email).await
+}
 
-    if status == StatusCode::CREATED {
-        let json: AssetResponse = resp.json().await?;
-        Ok(Some(json.id))
-    } else if status == StatusCode::OK {
-        warn!("File exists (Deduplicated): {}", filename);
-        let json: AssetResponse = resp.json().await?;
-        Ok(Some(json.id))
-    } else if status == StatusCode::CONFLICT {
-        warn!("Duplicate rejected: {}", filename);
-        // Try to parse ID from error body if possible, otherwise return generic flag
-        match resp.json::<AssetResponse>().await {
-            Ok(json) => Ok(Some(json.id)),
-            Err(_) => Ok(Some("DUPLICATE_UNKNOWN_ID".to_string()))
+async fn history_command(action: &HistoryAction) -> Result<()> {
+    let mut history = History::open()?;
+    match action {
+        HistoryAction::List { filter } => {
+            let records = history.records()?;
+            let shown: Vec<&Record> = records
+                .iter()
+                .filter(|r| filter.as_ref().is_none_or(|f| r.name.contains(f.as_str()) || r.path.as_ref().is_some_and(|p| p.contains(f.as_str()))))
+                .collect();
+            for record in &shown {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    record.path.as_deref().unwrap_or(&record.name),
+                    record.uploaded_at.to_rfc3339(),
+                    record.asset_id.as_deref().unwrap_or("-"),
+                    record.sha1.as_deref().unwrap_or("-")
+                );
+            }
+            println!("\n{} of {} entries shown", shown.len(), records.len());
+        }
+        HistoryAction::Prune { dry_run } => {
+            let config = Config::from_env()?;
+            let mut local = HashSet::new();
+            let mut scan = scan::spawn_scan(&config, None);
+            while let Some(path) = scan.recv().await {
+                local.insert(path.file_name().unwrap().to_string_lossy().to_string());
+            }
+            let gone: Vec<Record> = history.records()?.into_iter().filter(|r| !local.contains(&r.name)).collect();
+            for record in &gone {
+                println!("{}", record.path.as_deref().unwrap_or(&record.name));
+            }
+            if !dry_run {
+                history.forget(&gone)?;
+            }
+            println!("\n{} {} entries for files no longer found", if *dry_run { "Would forget" } else { "Forgot" }, gone.len());
+        }
+        HistoryAction::Forget { pattern } => {
+            let matches = |text: &str| rules::wildcard(pattern.as_bytes(), text.as_bytes());
+            let matched: Vec<Record> = history
+                .records()?
+                .into_iter()
+                .filter(|r| matches(&r.name) || r.path.as_deref().is_some_and(matches))
+                .collect();
+            history.forget(&matched)?;
+            println!("Forgot {} entries", matched.len());
+        }
+        HistoryAction::Export => {
+            serde_json::to_writer_pretty(std::io::stdout().lock(), &history.records()?)?;
+            println!();
+        }
+        HistoryAction::Import { file } => {
+            let records: Vec<Record> = if file.as_os_str() == "-" {
+                serde_json::from_reader(std::io::stdin().lock())?
+            } else {
+                serde_json::from_reader(File::open(file)?)?
+            };
+            history.insert(&records)?;
+            println!("Imported {} entries", records.len());
         }
-    } else {
-        let error_text = resp.text().await?;
-        error!("Upload failed for {}: Status {} - {}", filename, status, error_text);
-        Ok(None)
     }
+    Ok(())
 }
 
-fn load_history() -> Result<HashSet<String>> {
-    if Path::new(HISTORY_FILE).exists() {
-        let file = File::open(HISTORY_FILE)?;
-        let history: Vec<String> = serde_json::from_reader(file).unwrap_or_default();
-        return Ok(history.into_iter().collect());
+fn dead_letter_command(action: &DeadLetterAction) -> Result<()> {
+    let mut dead_letters = dead_letter::DeadLetters::load();
+    match action {
+        DeadLetterAction::List => {
+            for (key, failure) in dead_letters.iter() {
+                println!(
+                    "{}\t{} attempt(s)\tlast {}\t{}",
+                    history::key_path(key).display(),
+                    failure.attempts,
+                    failure.last_attempt.to_rfc3339(),
+                    failure.last_error
+                );
+            }
+        }
+        DeadLetterAction::Reset { name: Some(name) } => match dead_letters.clear_matching(name) {
+            0 => println!("No failures recorded for {}", name),
+            1 => {}
+            n => println!("Reset {} files matching {}", n, name),
+        },
+        DeadLetterAction::Reset { name: None } => {
+            println!("Reset {} file(s)", dead_letters.clear_all());
+        }
     }
-    Ok(HashSet::new())
+    dead_letters.save()
 }
 
-fn save_history(history: &HashSet<String>) -> Result<()> {
-    let file = File::create(HISTORY_FILE)?;
-    let list: Vec<&String> = history.iter().collect();
-    serde_json::to_writer_pretty(file, &list)?;
-    Ok(())
-}
\ No newline at end of file
+/// One client for the whole process: clones share its connection pool, and
+/// `connections::acquire` keeps any one host from taking all of it.
+fn build_client(config: &Config) -> Result<Client> {
+    connections::set_per_host(config.max_connections_per_host);
+    let pool = config.connection.pool_max_idle_per_host.unwrap_or(config.max_connections_per_host);
+    let builder = Client::builder().pool_max_idle_per_host(pool);
+    Ok(client::configure(builder, &config.connection)?.build()?)
+}
@@ -0,0 +1,56 @@
+use crate::config::SourceFolder;
+use crate::rules::wildcard;
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::path::Path;
+
+/// Looked for in the working directory, then the config folder, when `IMMICH_MAPPINGS_FILE`
+/// isn't set.
+pub const DEFAULT_MAPPINGS_FILE: &str = "mappings.toml";
+
+/// Path globs to album names, e.g. `"**/Memes/**" = "Memes"`. Globs are matched against
+/// the path below the synced folder: `*` and `?` stay within one directory, `**` spans
+/// any number of them. The first matching line wins.
+#[derive(Clone, Default)]
+pub struct Mappings {
+    globs: Vec<(String, String)>,
+}
+
+impl Mappings {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("Failed to read mappings file {}", path.display()))?;
+        let table: toml::Table = toml::from_str(&text).with_context(|| format!("Invalid mappings file {}", path.display()))?;
+        let mut globs = Vec::new();
+        for (glob, album) in table {
+            let Some(album) = album.as_str() else {
+                bail!("Invalid mappings file {}: the album for '{}' must be a string", path.display(), glob);
+            };
+            globs.push((glob.replace('\\', "/"), album.to_string()));
+        }
+        Ok(Self { globs })
+    }
+
+    /// The album the first matching glob maps the file to.
+    pub fn album_for(&self, path: &Path, roots: &[SourceFolder]) -> Option<&str> {
+        if self.globs.is_empty() {
+            return None;
+        }
+        let relative = roots.iter().find_map(|root| path.strip_prefix(&root.path).ok()).unwrap_or(path);
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        let segments: Vec<&str> = relative.split('/').filter(|s| !s.is_empty()).collect();
+        self.globs
+            .iter()
+            .find(|(glob, _)| glob_match(&glob.split('/').filter(|s| !s.is_empty()).collect::<Vec<_>>(), &segments))
+            .map(|(_, album)| album.as_str())
+    }
+}
+
+fn glob_match(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| glob_match(rest, &path[skip..])),
+        Some((segment, rest)) => {
+            path.split_first().is_some_and(|(name, tail)| wildcard(segment.as_bytes(), name.as_bytes()) && glob_match(rest, tail))
+        }
+    }
+}
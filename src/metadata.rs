@@ -0,0 +1,168 @@
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use crate::scan::sidecar_for;
+use exif::{Context, Exif, In, Reader, Tag, Value};
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+/// Reads the EXIF block of an image; `None` for files without one (or not images).
+pub fn read_exif(path: &Path) -> Option<Exif> {
+    let file = File::open(path).ok()?;
+    Reader::new().read_from_container(&mut BufReader::new(file)).ok()
+}
+
+/// Like `read_exif`, for a file already in memory.
+pub fn read_exif_bytes(data: &[u8]) -> Option<Exif> {
+    Reader::new().read_from_container(&mut std::io::Cursor::new(data)).ok()
+}
+
+/// When the photo was taken, from `DateTimeOriginal`. Uses `OffsetTimeOriginal` when
+/// the camera recorded one; otherwise the time is taken to be in the local timezone.
+pub fn capture_time(exif: &Exif) -> Option<DateTime<FixedOffset>> {
+    let mut dt = exif::DateTime::from_ascii(ascii_field(exif, Tag::DateTimeOriginal)?).ok()?;
+    if let Some(subsec) = ascii_field(exif, Tag::SubSecTimeOriginal) {
+        let _ = dt.parse_subsec(subsec);
+    }
+    if let Some(offset) = ascii_field(exif, Tag::OffsetTimeOriginal) {
+        let _ = dt.parse_offset(offset);
+    }
+
+    let naive = NaiveDate::from_ymd_opt(dt.year.into(), dt.month.into(), dt.day.into())?.and_hms_nano_opt(
+        dt.hour.into(),
+        dt.minute.into(),
+        dt.second.into(),
+        dt.nanosecond.unwrap_or(0),
+    )?;
+    match dt.offset {
+        Some(minutes) => FixedOffset::east_opt(i32::from(minutes) * 60)?.from_local_datetime(&naive).single(),
+        None => Local.from_local_datetime(&naive).earliest().map(|t| t.fixed_offset()),
+    }
+}
+
+/// When the file was captured: the EXIF date, else a date in the filename.
+pub fn taken_at(path: &Path, patterns: &[String]) -> Option<DateTime<FixedOffset>> {
+    read_exif(path).and_then(|exif| capture_time(&exif)).or_else(|| filename_time(path, patterns))
+}
+
+/// Capture time embedded in a filename, e.g. `Screenshot 2023-08-14 at 10.33.12.png`
+/// or `IMG-20230814-WA0012.jpg`. `patterns` are chrono formats tried in order, each at
+/// every position where a number starts; date-only patterns give local midnight.
+pub fn filename_time(path: &Path, patterns: &[String]) -> Option<DateTime<FixedOffset>> {
+    let stem = path.file_stem()?.to_string_lossy();
+    let starts: Vec<usize> = stem
+        .char_indices()
+        .filter(|&(i, c)| c.is_ascii_digit() && !stem[..i].ends_with(|p: char| p.is_ascii_digit()))
+        .map(|(i, _)| i)
+        .collect();
+    let naive = patterns.iter().find_map(|pattern| {
+        starts.iter().find_map(|&i| {
+            NaiveDateTime::parse_and_remainder(&stem[i..], pattern)
+                .map(|(dt, _)| dt)
+                .or_else(|_| NaiveDate::parse_and_remainder(&stem[i..], pattern).map(|(d, _)| d.and_time(NaiveTime::MIN)))
+                .ok()
+        })
+    })?;
+    Local.from_local_datetime(&naive).earliest().map(|t| t.fixed_offset())
+}
+
+fn ascii_field(exif: &Exif, tag: Tag) -> Option<&[u8]> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(parts) => parts.first().map(|p| p.as_slice()),
+        _ => None,
+    }
+}
+
+/// Camera make and model, e.g. `Google Pixel 7`.
+pub fn camera(exif: &Exif) -> Option<String> {
+    let text = |tag| ascii_field(exif, tag).map(|v| String::from_utf8_lossy(v).trim().to_string());
+    match (text(Tag::Make), text(Tag::Model)) {
+        (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+        (make, model) => make.or(model),
+    }
+}
+
+pub fn has_gps(exif: &Exif) -> bool {
+    exif.get_field(Tag::GPSLatitude, In::PRIMARY).is_some()
+}
+
+/// The Windows/Adobe star rating tag, which the exif crate doesn't predefine.
+const RATING: Tag = Tag(Context::Tiff, 0x4746);
+// Embedded XMP sits in the first segments of the file; don't read whole videos.
+const XMP_SEARCH_BYTES: u64 = 512 * 1024;
+
+/// Star rating (0-5) from an XMP sidecar, embedded XMP or the EXIF `Rating` tag.
+pub fn rating(path: &Path) -> Option<u32> {
+    xmp_packets(path)
+        .iter()
+        .find_map(|xmp| xmp_property(xmp, "xmp:Rating"))
+        .and_then(|r| r.trim().parse::<f32>().ok())
+        .map(|r| r.max(0.0) as u32)
+        .or_else(|| read_exif(path)?.get_field(RATING, In::PRIMARY)?.value.get_uint(0))
+}
+
+/// Keywords (`dc:subject`, plus Lightroom's `lr:hierarchicalSubject`) from XMP.
+pub fn keywords(path: &Path) -> Vec<String> {
+    let mut keywords = Vec::new();
+    for xmp in xmp_packets(path) {
+        for property in ["dc:subject", "lr:hierarchicalSubject"] {
+            let Some(body) = xmp.split_once(&format!("<{}>", property)).and_then(|(_, r)| r.split_once(&format!("</{}>", property)))
+            else {
+                continue;
+            };
+            keywords.extend(
+                body.0
+                    .split("<rdf:li>")
+                    .skip(1)
+                    .filter_map(|li| li.split_once("</rdf:li>"))
+                    .map(|(kw, _)| kw.trim().to_string()),
+            );
+        }
+    }
+    keywords
+}
+
+/// Whether a photo carries an embedded video: a Google Motion Photo (Pixel, newer Samsung
+/// phones, flagged in XMP) or an older Samsung one (a `MotionPhoto_Data` trailer).
+/// Immich extracts the video itself, as long as the file reaches it unchanged.
+pub fn is_motion_photo(data: &[u8]) -> bool {
+    let head = String::from_utf8_lossy(&data[..data.len().min(XMP_SEARCH_BYTES as usize)]);
+    if ["GCamera:MotionPhoto", "GCamera:MicroVideo"]
+        .iter()
+        .any(|flag| xmp_property(&head, flag).is_some_and(|v| v.trim() == "1"))
+    {
+        return true;
+    }
+    data.windows(16).any(|w| w == b"MotionPhoto_Data")
+}
+
+/// Whether the XMP in `data` (a sidecar, or the start of a file) has a location.
+pub fn xmp_has_gps(data: &[u8]) -> bool {
+    let head = String::from_utf8_lossy(&data[..data.len().min(XMP_SEARCH_BYTES as usize)]);
+    ["exif:GPSLatitude", "exif:GPSLongitude"]
+        .iter()
+        .any(|property| xmp_property(&head, property).is_some_and(|v| !v.trim().is_empty()))
+}
+
+/// XMP from the sidecar (which wins, as editors write there) and the file itself.
+fn xmp_packets(path: &Path) -> Vec<String> {
+    let mut packets: Vec<String> = sidecar_for(path).and_then(|s| fs::read_to_string(s).ok()).into_iter().collect();
+    let mut head = Vec::new();
+    if File::open(path).and_then(|f| f.take(XMP_SEARCH_BYTES).read_to_end(&mut head)).is_ok() {
+        let text = String::from_utf8_lossy(&head);
+        if let Some(start) = text.find("<x:xmpmeta")
+            && let Some(len) = text[start..].find("</x:xmpmeta>")
+        {
+            packets.push(text[start..start + len].to_string());
+        }
+    }
+    packets
+}
+
+/// A simple XMP property, written either as `prop="v"` or `<prop>v</prop>`.
+fn xmp_property<'a>(xmp: &'a str, name: &str) -> Option<&'a str> {
+    if let Some((_, rest)) = xmp.split_once(&format!("{}=\"", name)) {
+        return rest.split_once('"').map(|(v, _)| v);
+    }
+    let (_, rest) = xmp.split_once(&format!("<{}>", name))?;
+    rest.split_once(&format!("</{}>", name)).map(|(v, _)| v)
+}
@@ -0,0 +1,80 @@
+use crate::health::Health;
+use crate::status::Status;
+use anyhow::{Context, Result};
+use log::{debug, info};
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Serves `/metrics` in the Prometheus text format on `addr`, for alerting when
+/// syncing stops. Counters start at zero with the process; the last success and the
+/// backlog come from the health file, so they survive restarts.
+pub fn spawn_server(addr: SocketAddr, status: Arc<Status>) -> Result<()> {
+    let listener = std::net::TcpListener::bind(addr).with_context(|| format!("Could not listen for metrics on {}", addr))?;
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+    info!("Serving metrics at http://{}/metrics", addr);
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let status = status.clone();
+            tokio::spawn(async move {
+                if let Err(e) = respond(stream, &status).await {
+                    debug!("Metrics request failed: {:?}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+async fn respond(mut stream: TcpStream, status: &Status) -> Result<()> {
+    // Only the request line matters; headers are read so the client isn't cut off mid-send
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 8192 {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let path = request.split_whitespace().nth(1).unwrap_or_default();
+    let (code, body) = match path.split('?').next() {
+        Some("/metrics") => ("200 OK", render(status)),
+        _ => ("404 Not Found", "Not found; try /metrics\n".to_string()),
+    };
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        code,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    Ok(())
+}
+
+fn render(status: &Status) -> String {
+    let totals = status.totals();
+    let health = Health::load();
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: f64| {
+        let _ = writeln!(out, "# HELP immich_sync_{} {}\n# TYPE immich_sync_{} {}\nimmich_sync_{} {}", name, help, name, kind, name, value);
+    };
+    metric("uploads_total", "counter", "Files uploaded since the process started.", totals.uploads as f64);
+    metric("failures_total", "counter", "Files that failed to upload since the process started.", totals.failures as f64);
+    metric("uploaded_bytes_total", "counter", "Bytes sent to the server since the process started.", totals.bytes as f64);
+    metric("queue_depth", "gauge", "Files waiting for an upload slot.", status.queue_depth() as f64);
+    metric("active_uploads", "gauge", "Uploads in progress.", status.active_uploads() as f64);
+    metric("backlog_files", "gauge", "Files not uploaded yet as of the last full scan.", health.backlog as f64);
+    metric("consecutive_failures", "gauge", "Sync passes that failed in a row.", health.consecutive_failures as f64);
+    metric("paused", "gauge", "1 while uploads are paused.", if status.is_paused() { 1.0 } else { 0.0 });
+    // Absent until the first success, so "never synced" can't pass for 1970
+    if let Some(at) = health.last_success {
+        metric("last_success_timestamp_seconds", "gauge", "When the last sync pass succeeded (Unix time).", at.timestamp() as f64);
+    }
+    out
+}
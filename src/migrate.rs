@@ -0,0 +1,82 @@
+use crate::api::{find_all_by_checksum, find_by_file_name, get_active_url, validate_key};
+use crate::config::Config;
+use crate::history::{History, Record};
+use crate::scan::spawn_scan;
+use anyhow::{Result, bail};
+use log::{info, warn};
+use reqwest::Client;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+// Files looked up per bulk-upload-check request
+const CHECK_BATCH: usize = 500;
+
+/// `migrate`: moves an old `immich_upload_history.json` into the state database (opening
+/// the history does that) and fills in what the old format didn't keep, so nothing gets
+/// uploaded again. Bare filenames get their hash from the local file of that name; entries
+/// are then tied to server assets by checksum, or failing that by a unique filename.
+pub async fn run(client: &Client, config: &Config) -> Result<()> {
+    let Some(base_url) = get_active_url(client, &config.local_url, &config.ext_url).await else {
+        bail!("Could not connect to any Immich instance.");
+    };
+    let key = &config.api_key;
+    validate_key(client, &base_url, key).await?;
+    let mut history = History::open()?;
+
+    let unhashed: Vec<String> = history.unresolved()?.into_iter().filter(|(_, _, sha1)| sha1.is_none()).map(|(_, name, _)| name).collect();
+    if !unhashed.is_empty() {
+        let mut local: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        let mut scan = spawn_scan(config, None);
+        while let Some(path) = scan.recv().await {
+            local.entry(path.file_name().unwrap().to_string_lossy().to_string()).or_default().push(path);
+        }
+        let mut hashed = 0;
+        for name in &unhashed {
+            // Two local files of that name: no telling which one was uploaded
+            let Some([path]) = local.get(name).map(Vec::as_slice) else {
+                continue;
+            };
+            match history.content(path) {
+                Ok(content) => {
+                    history.find(&config.job_for(path), name, &content)?;
+                    history.insert(&[Record::new(config, path, content, None)])?;
+                    hashed += 1;
+                }
+                Err(e) => warn!("Failed to hash {}: {:?}", path.display(), e),
+            }
+        }
+        info!("Hashed {} of {} history entries that only had a filename.", hashed, unhashed.len());
+    }
+
+    let unresolved = history.unresolved()?;
+    let mut resolved = 0;
+    let hashed: Vec<(&str, &str, &str)> =
+        unresolved.iter().filter_map(|(job, name, sha1)| Some((job.as_str(), name.as_str(), sha1.as_deref()?))).collect();
+    for batch in hashed.chunks(CHECK_BATCH) {
+        let files: Vec<(&str, &str)> = batch.iter().map(|(_, name, sha1)| (*name, *sha1)).collect();
+        let found = find_all_by_checksum(client, &base_url, key, &files).await?;
+        for (job, name, sha1) in batch {
+            if let Some(m) = found.get(*name) {
+                history.set_asset(job, name, Some(sha1), &m.asset_id)?;
+                resolved += 1;
+            }
+        }
+    }
+    for (job, name, _) in unresolved.iter().filter(|(_, _, sha1)| sha1.is_none()) {
+        match find_by_file_name(client, &base_url, key, name).await?.as_slice() {
+            [asset_id] => {
+                history.set_asset(job, name, None, asset_id)?;
+                resolved += 1;
+            }
+            [] => {}
+            _ => warn!("Several assets are named {}, leaving it unresolved", name),
+        }
+    }
+    info!(
+        "Tied {} of {} history entries to their server asset; {} left as they were.",
+        resolved,
+        unresolved.len(),
+        unresolved.len() - resolved
+    );
+    Ok(())
+}
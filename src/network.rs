@@ -0,0 +1,71 @@
+use log::debug;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+use tokio::sync::Mutex;
+
+/// How long an answer from the OS is reused. Uploads check before every file, and
+/// starting busctl (or PowerShell) for each would cost more than the check is worth.
+const PROBE_TTL: Duration = Duration::from_secs(30);
+
+// Async so concurrent uploads wait for one probe rather than each starting their own
+static LAST_PROBE: Mutex<Option<(Instant, Option<bool>)>> = Mutex::const_new(None);
+
+/// Asks the OS whether the active connection is metered (mobile hotspot, capped plan).
+/// Returns `None` when this can't be determined on the current platform.
+pub async fn is_metered() -> Option<bool> {
+    let mut last = LAST_PROBE.lock().await;
+    if let Some((at, metered)) = *last
+        && at.elapsed() < PROBE_TTL
+    {
+        return metered;
+    }
+    let metered = probe().await;
+    *last = Some((Instant::now(), metered));
+    metered
+}
+
+async fn probe() -> Option<bool> {
+    if cfg!(target_os = "linux") {
+        // NetworkManager's global Metered property: 1 = yes, 3 = guessed yes
+        let out = run(
+            "busctl",
+            &[
+                "--system",
+                "get-property",
+                "org.freedesktop.NetworkManager",
+                "/org/freedesktop/NetworkManager",
+                "org.freedesktop.NetworkManager",
+                "Metered",
+            ],
+        )
+        .await?;
+        match out.split_whitespace().nth(1)? {
+            "1" | "3" => Some(true),
+            "2" | "4" => Some(false),
+            _ => None,
+        }
+    } else if cfg!(windows) {
+        let script = "[Windows.Networking.Connectivity.NetworkInformation,Windows.Networking.Connectivity,ContentType=WindowsRuntime]::GetInternetConnectionProfile().GetConnectionCost().NetworkCostType";
+        match run("powershell", &["-NoProfile", "-Command", script]).await?.trim() {
+            "Fixed" | "Variable" => Some(true),
+            "Unrestricted" => Some(false),
+            _ => None,
+        }
+    } else {
+        None
+    }
+}
+
+async fn run(program: &str, args: &[&str]) -> Option<String> {
+    match Command::new(program).args(args).output().await {
+        Ok(out) if out.status.success() => Some(String::from_utf8_lossy(&out.stdout).into_owned()),
+        Ok(out) => {
+            debug!("{} exited with {}", program, out.status);
+            None
+        }
+        Err(e) => {
+            debug!("Could not run {}: {}", program, e);
+            None
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use crate::config::{Config, NotifyOn, PushTarget};
+use crate::email;
+use crate::summary::Summary;
+use log::{debug, warn};
+use serde_json::{Value, json};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// For notification services. Not the Immich client: a pinned certificate or client
+/// certificate is meant for the server only.
+pub static CLIENT: LazyLock<reqwest::Client> =
+    LazyLock::new(|| reqwest::Client::builder().timeout(Duration::from_secs(10)).build().unwrap_or_default());
+
+/// Failed files named in a notification; the rest are only counted.
+const MAX_LISTED_FAILURES: usize = 10;
+const TELEGRAM_MAX_CHARS: usize = 4096;
+
+// When the last Telegram message went out, and how many were held back since
+static TELEGRAM_THROTTLE: Mutex<(Option<Instant>, usize)> = Mutex::new((None, 0));
+
+/// A pass failing with the error that was last pushed isn't pushed again within this
+/// window, so a server that stays down doesn't page every interval.
+const FAILURE_REPEAT_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+// The last failure pushed, and when; cleared by a pass that succeeds
+static LAST_PUSHED_FAILURE: Mutex<Option<(Instant, String)>> = Mutex::new(None);
+
+/// Tells the configured services how a pass ended. `summary` is missing when the pass
+/// failed before it got to uploading; `failure` is the error that failed it, if any.
+pub async fn run_finished(config: &Config, summary: Option<&Summary>, failure: Option<&str>) {
+    if let Some(url) = &config.webhook_url {
+        post_webhook(url, run_payload(summary, failure)).await;
+    }
+    let uploaded = summary.is_some_and(|s| s.uploaded > 0);
+    let wanted = match config.notify_on {
+        NotifyOn::Always => true,
+        NotifyOn::Changes => uploaded || failure.is_some(),
+        NotifyOn::Failure => failure.is_some(),
+    };
+    if !wanted {
+        return;
+    }
+    if repeats_pushed_failure(failure) {
+        debug!("Not notifying again about the same failure.");
+        return;
+    }
+    let (title, body) = message(summary, failure);
+    for target in &config.push_targets {
+        push(target, &title, &body, failure.is_some()).await;
+    }
+    if let Some(settings) = &config.email
+        && let Err(e) = email::send(settings, &title, summary, failure).await
+    {
+        warn!("Failed to email the report: {:#}", e);
+    }
+}
+
+/// True if `failure` is the one last pushed, within `FAILURE_REPEAT_WINDOW`. Otherwise
+/// it is remembered as the last one.
+fn repeats_pushed_failure(failure: Option<&str>) -> bool {
+    let mut last = LAST_PUSHED_FAILURE.lock().unwrap();
+    let Some(error) = failure else {
+        *last = None;
+        return false;
+    };
+    if let Some((at, pushed)) = &*last
+        && pushed == error
+        && at.elapsed() < FAILURE_REPEAT_WINDOW
+    {
+        return true;
+    }
+    *last = Some((Instant::now(), error.to_string()));
+    false
+}
+
+/// Title and text of a push notification.
+fn message(summary: Option<&Summary>, failure: Option<&str>) -> (String, String) {
+    let title = match (failure, summary) {
+        (Some(_), _) => "Immich sync failed".to_string(),
+        (None, Some(s)) => format!("Immich sync: {} new file(s)", s.uploaded),
+        (None, None) => "Immich sync finished".to_string(),
+    };
+    (title, report(summary, failure))
+}
+
+/// The summary as text with the first few failed files, then the error, if any.
+pub fn report(summary: Option<&Summary>, failure: Option<&str>) -> String {
+    let mut body = summary.map(|s| format!("Run {}\n{}", s.run_id, s)).unwrap_or_default();
+    if let Some(s) = summary.filter(|s| !s.failures.is_empty()) {
+        body.push_str("\n\nFailed:");
+        for (file, error) in s.failures.iter().take(MAX_LISTED_FAILURES) {
+            body.push_str(&format!("\n{}: {}", file, error));
+        }
+        if s.failures.len() > MAX_LISTED_FAILURES {
+            body.push_str(&format!("\n...and {} more", s.failures.len() - MAX_LISTED_FAILURES));
+        }
+    }
+    match failure {
+        Some(error) => format!("{}\n\nLast error: {}", body, error).trim_start().to_string(),
+        None => body,
+    }
+}
+
+/// Whether a Telegram message may go out now; if so, how many were held back since
+/// the last one.
+fn telegram_slot(min_interval: Duration) -> Option<usize> {
+    let mut throttle = TELEGRAM_THROTTLE.lock().unwrap();
+    let (last_sent, held_back) = &mut *throttle;
+    if last_sent.is_some_and(|at| at.elapsed() < min_interval) {
+        *held_back += 1;
+        return None;
+    }
+    *last_sent = Some(Instant::now());
+    Some(std::mem::take(held_back))
+}
+
+async fn push(target: &PushTarget, title: &str, body: &str, failed: bool) {
+    let request = match target {
+        PushTarget::Ntfy { topic_url, token } => {
+            let request = CLIENT
+                .post(topic_url)
+                .header("Title", title)
+                .header("Priority", if failed { "high" } else { "default" })
+                .header("Tags", if failed { "warning" } else { "white_check_mark" })
+                .body(body.to_string());
+            match token {
+                Some(token) => request.bearer_auth(token),
+                None => request,
+            }
+        }
+        PushTarget::Gotify { url, token } => CLIENT
+            .post(format!("{}/message", url))
+            .header("X-Gotify-Key", token)
+            .json(&json!({ "title": title, "message": body, "priority": if failed { 8 } else { 4 } })),
+        PushTarget::Telegram { token, chat_id, min_interval } => {
+            let Some(held_back) = telegram_slot(*min_interval) else {
+                return;
+            };
+            let mut text = format!("{}\n\n{}", title, body);
+            if held_back > 0 {
+                text.push_str(&format!("\n\n({} earlier notification(s) held back)", held_back));
+            }
+            let text: String = text.chars().take(TELEGRAM_MAX_CHARS).collect();
+            CLIENT
+                .post(format!("https://api.telegram.org/bot{}/sendMessage", token))
+                .json(&json!({ "chat_id": chat_id, "text": text }))
+        }
+    };
+    // Without the URL, which carries the Telegram bot token
+    if let Err(e) = request.send().await.and_then(|r| r.error_for_status()) {
+        warn!("Push notification failed: {}", e.without_url());
+    }
+}
+
+/// With `IMMICH_WEBHOOK_ON_FAILURE`, reports a failed upload right away rather than
+/// at the end of the pass. Sent in the background so a slow hook can't hold up uploads.
+pub fn upload_failed(config: &Config, run_id: &str, file: &str, error: &anyhow::Error) {
+    let Some(url) = config.webhook_url.clone().filter(|_| config.webhook_on_failure) else {
+        return;
+    };
+    let payload = json!({ "event": "upload_failed", "run_id": run_id, "file": file, "error": format!("{:#}", error) });
+    tokio::spawn(async move { post_webhook(&url, payload).await });
+}
+
+fn run_payload(summary: Option<&Summary>, failure: Option<&str>) -> Value {
+    let failures: Option<Vec<Value>> =
+        summary.map(|s| s.failures.iter().map(|(file, error)| json!({ "file": file, "error": error })).collect());
+    json!({
+        "event": "run_finished",
+        "ok": failure.is_none(),
+        "error": failure,
+        "run_id": summary.map(|s| &s.run_id),
+        "scanned": summary.map(|s| s.scanned),
+        "in_history": summary.map(|s| s.in_history),
+        "duplicates": summary.map(|s| s.duplicates),
+        "new_assets": summary.map(|s| s.uploaded),
+        "failed": summary.map(|s| s.failed),
+        "skipped": summary.map(|s| s.skipped),
+        "bytes": summary.map(|s| s.bytes),
+        "duration_secs": summary.map(|s| s.elapsed.as_secs_f64()),
+        "failures": failures,
+    })
+}
+
+async fn post_webhook(url: &str, payload: Value) {
+    let result = CLIENT.post(url).json(&payload).send().await.and_then(|r| r.error_for_status());
+    if let Err(e) = result {
+        warn!("Webhook {} failed: {}", url, e);
+    }
+}
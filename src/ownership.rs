@@ -0,0 +1,19 @@
+use crate::api::{DEVICE_ID, get_asset, get_my_user_id};
+use anyhow::{Result, bail};
+use reqwest::Client;
+
+/// Refuses to let a destructive operation (delete, replace, unlink) touch an asset
+/// unless it belongs to the authenticated user and was uploaded by this tool.
+pub async fn ensure_ours(client: &Client, base_url: &str, key: &str, asset_id: &str) -> Result<()> {
+    let Some(asset) = get_asset(client, base_url, key, asset_id).await? else {
+        bail!("Asset {} not found on server", asset_id);
+    };
+    let me = get_my_user_id(client, base_url, key).await?;
+    if asset.owner_id != me {
+        bail!("Refusing to modify asset {}: owned by another user", asset_id);
+    }
+    if asset.device_id != DEVICE_ID {
+        bail!("Refusing to modify asset {}: uploaded by another client ({})", asset_id, asset.device_id);
+    }
+    Ok(())
+}
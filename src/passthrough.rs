@@ -0,0 +1,34 @@
+use crate::api::{Authed, get_active_url};
+use crate::config::Config;
+use anyhow::{Context, Result, bail};
+use reqwest::{Client, Method};
+
+/// `api <METHOD> <PATH>`: sends an authenticated request to the same server the
+/// sync would use and pretty-prints the response.
+pub async fn run(client: &Client, config: &Config, method: &str, path: &str, data: Option<&str>) -> Result<()> {
+    let method = Method::from_bytes(method.to_uppercase().as_bytes()).context("Invalid HTTP method")?;
+    let Some(base_url) = get_active_url(client, &config.local_url, &config.ext_url).await else {
+        bail!("Could not connect to any Immich instance.");
+    };
+    let path = path.trim_start_matches('/');
+    let path = path.strip_prefix("api/").unwrap_or(path);
+
+    let mut request = client.request(method, format!("{}/api/{}", base_url, path)).authed(&config.api_key);
+    if let Some(data) = data {
+        let body: serde_json::Value = serde_json::from_str(data).context("--data must be valid JSON")?;
+        request = request.json(&body);
+    }
+    let resp = request.send().await?;
+    let status = resp.status();
+    let text = resp.text().await?;
+
+    eprintln!("{}", status);
+    match serde_json::from_str::<serde_json::Value>(&text) {
+        Ok(json) => println!("{}", serde_json::to_string_pretty(&json)?),
+        Err(_) => println!("{}", text),
+    }
+    if !status.is_success() {
+        bail!("Request failed with {}", status);
+    }
+    Ok(())
+}
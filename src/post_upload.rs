@@ -0,0 +1,89 @@
+use crate::albums;
+use crate::api::{AssetUpdate, Uploader, update_asset};
+use crate::config::Config;
+use crate::gpx::Tracks;
+use crate::metadata::{capture_time, filename_time, has_gps, read_exif, taken_at};
+use log::{debug, info, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Works out what the server should know about a freshly linked asset beyond what the
+/// upload carried, and sends all of it in one update. `favorite` is for assets that were
+/// already on the server, which the upload's own favorite flag never reached.
+pub async fn update_metadata(uploader: &Uploader, config: &Config, tracks: Option<&Tracks>, path: &Path, asset_id: &str, favorite: bool) {
+    let mut update = AssetUpdate {
+        description: sidecar_description(path)
+            .or_else(|| config.description_template.as_ref().map(|t| description(t, path, &config.filename_date_patterns))),
+        ..AssetUpdate::default()
+    };
+    if favorite {
+        update.is_favorite = Some(true);
+    }
+    if config.set_capture_date {
+        update.date_time_original = corrected_date(path, &config.filename_date_patterns);
+    }
+    if let Some(tracks) = tracks {
+        (update.latitude, update.longitude) = geotag(tracks, config, path).unzip();
+    }
+
+    let fields = update.fields();
+    if fields.is_empty() {
+        return;
+    }
+    match update_asset(&uploader.client, &uploader.base_url, &uploader.key, asset_id, &update).await {
+        Ok(()) => info!("   -- Set {} of {}", fields.join(", "), path.display()),
+        Err(e) => warn!("Failed to set {} of {}: {:?}", fields.join(", "), path.display(), e),
+    }
+}
+
+/// `IMMICH_DESCRIPTION_TEMPLATE` filled in: album-name date placeholders plus
+/// `{filename}` and `{folder}` (the directory the file is in).
+fn description(template: &str, path: &Path, date_patterns: &[String]) -> String {
+    let folder = path.parent().and_then(|p| p.file_name()).map(|f| f.to_string_lossy()).unwrap_or_default();
+    albums::render(template, path, date_patterns)
+        .replace("{filename}", &path.file_name().unwrap().to_string_lossy())
+        .replace("{folder}", &folder)
+}
+
+/// A caption kept next to the file: the text of `photo.jpg.txt`, or the `description`
+/// of `photo.jpg.json` or `photo.json`. Wins over `IMMICH_DESCRIPTION_TEMPLATE`.
+fn sidecar_description(path: &Path) -> Option<String> {
+    let with_suffix = |suffix: &str| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(suffix);
+        PathBuf::from(name)
+    };
+    if let Ok(text) = fs::read_to_string(with_suffix(".txt")) {
+        return Some(text.trim().to_string()).filter(|t| !t.is_empty());
+    }
+    [with_suffix(".json"), path.with_extension("json")].iter().find_map(|json| {
+        let value: serde_json::Value = serde_json::from_str(&fs::read_to_string(json).ok()?)
+            .inspect_err(|e| warn!("Ignoring {}: {}", json.display(), e))
+            .ok()?;
+        let text = value.get("description")?.as_str()?.trim();
+        (!text.is_empty()).then(|| text.to_string())
+    })
+}
+
+/// The date in the filename, for files without an EXIF capture date; without it the
+/// server goes by whatever date the file system had.
+fn corrected_date(path: &Path, date_patterns: &[String]) -> Option<String> {
+    if read_exif(path).and_then(|exif| capture_time(&exif)).is_some() {
+        return None;
+    }
+    filename_time(path, date_patterns).map(|t| t.to_rfc3339())
+}
+
+/// Where the GPX tracks put the file at its capture time, unless it has GPS data.
+fn geotag(tracks: &Tracks, config: &Config, path: &Path) -> Option<(f64, f64)> {
+    if read_exif(path).is_some_and(|exif| has_gps(&exif)) {
+        return None;
+    }
+    let taken = taken_at(path, &config.filename_date_patterns)?;
+    let max_gap = chrono::Duration::from_std(config.gpx_max_gap).unwrap_or(chrono::Duration::MAX);
+    let location = tracks.locate(taken.to_utc(), max_gap);
+    if location.is_none() {
+        debug!("No track point near {} for {}", taken, path.display());
+    }
+    location
+}
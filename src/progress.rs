@@ -0,0 +1,90 @@
+use crate::status::Status;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::info;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// How often throughput goes to the log when there are no bars to show it.
+const LOG_EVERY: Duration = Duration::from_secs(60);
+
+// Hidden until `enable`, so cron runs, pipes and `--plain` only ever see log lines
+static BARS: LazyLock<MultiProgress> = LazyLock::new(|| MultiProgress::with_draw_target(ProgressDrawTarget::hidden()));
+
+/// Draws progress bars on stderr from now on. The returned handle is what the logger
+/// goes through, so log lines are printed above the bars rather than across them.
+pub fn enable() -> MultiProgress {
+    BARS.set_draw_target(ProgressDrawTarget::stderr());
+    BARS.clone()
+}
+
+fn add(bar: ProgressBar, template: &str) -> ProgressBar {
+    bar.set_style(ProgressStyle::with_template(template).unwrap().progress_chars("=> "));
+    BARS.add(bar)
+}
+
+/// The bars for one sync pass: files scanned so far, and of the files that needed
+/// uploading, how many are done along with the transfer rate and ETA. Both go away
+/// when the pass ends.
+pub struct Pass {
+    pub scanned: ProgressBar,
+    pub batch: ProgressBar,
+    reporter: JoinHandle<()>,
+}
+
+impl Pass {
+    pub fn start(status: &Arc<Status>) -> Self {
+        status.reset_transfer();
+        let scanned = add(ProgressBar::new_spinner(), "{spinner} Scanned {pos} file(s)");
+        scanned.enable_steady_tick(Duration::from_millis(120));
+        let batch = add(ProgressBar::new(0), "[{bar:30}] {pos}/{len} upload(s) done {msg}");
+        let reporter = tokio::spawn(report(status.clone(), batch.clone()));
+        Self { scanned, batch, reporter }
+    }
+}
+
+impl Drop for Pass {
+    fn drop(&mut self) {
+        self.reporter.abort();
+        remove(&self.scanned);
+        remove(&self.batch);
+    }
+}
+
+/// Keeps the batch bar's rate and ETA current, or logs them now and then for runs
+/// without bars.
+async fn report(status: Arc<Status>, batch: ProgressBar) {
+    let mut ticks = tokio::time::interval(Duration::from_secs(1));
+    let mut logged = Instant::now();
+    loop {
+        ticks.tick().await;
+        let Some(throughput) = status.throughput() else {
+            continue;
+        };
+        batch.set_message(throughput.to_string());
+        if BARS.is_hidden() && logged.elapsed() >= LOG_EVERY {
+            info!("Throughput: {}", throughput);
+            logged = Instant::now();
+        }
+    }
+}
+
+/// A bar for the bytes of one file being uploaded.
+pub fn file(name: &str, total: u64) -> ProgressBar {
+    let bar = add(ProgressBar::new(total), "  {msg:30!} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec}");
+    bar.set_message(name.to_string());
+    bar
+}
+
+pub fn remove(bar: &ProgressBar) {
+    bar.finish_and_clear();
+    BARS.remove(bar);
+}
+
+/// Runs an upload task and counts it, and its `bytes`, as done however it ends.
+pub async fn counted<T>(batch: ProgressBar, status: Arc<Status>, bytes: u64, task: impl Future<Output = T>) -> T {
+    let output = task.await;
+    status.finish_bytes(bytes);
+    batch.inc(1);
+    output
+}
@@ -0,0 +1,103 @@
+use crate::api::{Uploader, get_my_quota, get_server_storage};
+use crate::error::{SyncError, classify};
+use anyhow::Result;
+use log::{debug, warn};
+use std::time::{Duration, Instant};
+
+// Other clients upload too; re-read the usage this often during a long run
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const GB: f64 = (1024 * 1024 * 1024) as f64;
+
+/// One thing uploads fill up: the user's storage quota or the server's disk.
+struct Limit {
+    what: &'static str,
+    used: u64,
+    size: u64,
+}
+
+/// Keeps a run from uploading past the user's quota (or the server's free space).
+/// Files are admitted against the last known usage plus what this run has sent since.
+pub struct Quota {
+    limits: Vec<Limit>,
+    /// Share of each limit uploads may fill, from `IMMICH_QUOTA_STOP_PERCENT`
+    stop_at: f64,
+    pending: u64,
+    fetched_at: Instant,
+    warned: bool,
+}
+
+impl Quota {
+    /// `None` when the server reports neither (unlimited user, older server).
+    pub async fn fetch(uploader: &Uploader, stop_percent: Option<f64>) -> Option<Self> {
+        let mut quota = Self {
+            limits: Vec::new(),
+            stop_at: stop_percent.unwrap_or(100.0) / 100.0,
+            pending: 0,
+            fetched_at: Instant::now(),
+            warned: false,
+        };
+        if let Err(e) = quota.refresh(uploader).await {
+            debug!("Could not read storage usage, not checking it: {:?}", e);
+        }
+        for limit in &quota.limits {
+            debug!("{}: {:.1} of {:.1} GB used", limit.what, limit.used as f64 / GB, limit.size as f64 / GB);
+        }
+        (!quota.limits.is_empty()).then_some(quota)
+    }
+
+    async fn refresh(&mut self, uploader: &Uploader) -> Result<()> {
+        let (client, base_url, key) = (&uploader.client, &uploader.base_url, &uploader.key);
+        let mut limits = Vec::new();
+        let user = get_my_quota(client, base_url, key).await?;
+        if let Some(size) = user.quota_size_in_bytes {
+            limits.push(Limit { what: "Storage quota", used: user.quota_usage_in_bytes, size });
+        }
+        // Not every key may read it; the quota alone still helps
+        match get_server_storage(client, base_url, key).await {
+            Ok(disk) => limits.push(Limit { what: "Server disk", used: disk.disk_use_raw, size: disk.disk_size_raw }),
+            Err(e) => debug!("Could not read server disk usage: {:?}", e),
+        }
+        self.limits = limits;
+        // The new figures include whatever had finished uploading
+        self.pending = 0;
+        self.fetched_at = Instant::now();
+        Ok(())
+    }
+
+    /// Re-reads the usage once it's `REFRESH_INTERVAL` old; keeps the old figures on failure.
+    pub async fn refresh_if_stale(&mut self, uploader: &Uploader) {
+        if self.fetched_at.elapsed() >= REFRESH_INTERVAL
+            && let Err(e) = self.refresh(uploader).await
+        {
+            debug!("Could not refresh storage usage: {:?}", e);
+        }
+    }
+
+    /// Counts `bytes` against every limit; false (and nothing counted) if they won't fit.
+    pub fn try_reserve(&mut self, bytes: u64) -> bool {
+        let full = self
+            .limits
+            .iter()
+            .find(|l| (l.used + self.pending + bytes) as f64 > l.size as f64 * self.stop_at);
+        if let Some(limit) = full {
+            if !self.warned {
+                warn!(
+                    "{} won't fit the rest of this batch ({:.1} of {:.1} GB used{}); holding back uploads.",
+                    limit.what,
+                    (limit.used + self.pending) as f64 / GB,
+                    limit.size as f64 / GB,
+                    if self.stop_at < 1.0 { format!(", stopping at {:.0}%", self.stop_at * 100.0) } else { String::new() }
+                );
+                self.warned = true;
+            }
+            return false;
+        }
+        self.pending += bytes;
+        true
+    }
+}
+
+/// Whether an upload failed because the user is out of quota.
+pub fn is_quota_error(error: &anyhow::Error) -> bool {
+    matches!(classify(error), Some(SyncError::Quota { .. }))
+}
@@ -0,0 +1,88 @@
+use crate::state;
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::sync::Mutex;
+
+const RATE_BUDGET_FILE: &str = "immich_rate_budget.json";
+
+// Requests are charged where they're sent (`Authed`), which only knows the API key.
+// Rate limits are per key anyway, so mirrors and accounts each get their own budget.
+static BUDGETS: Mutex<BTreeMap<String, RateBudget>> = Mutex::new(BTreeMap::new());
+
+/// Loads the budget for `key` from the state folder, unless an earlier pass already
+/// did; every request sent with that key is charged to it from here on.
+pub fn start(key: &str, per_hour: u32) {
+    BUDGETS.lock().unwrap().entry(key.to_string()).or_insert_with(|| RateBudget::load(per_hour));
+}
+
+/// Counts one request sent with `key`, if it has a budget.
+pub fn charge(key: &str) {
+    if let Some(budget) = BUDGETS.lock().unwrap().get_mut(key) {
+        budget.expire();
+        budget.sent.push_back(Utc::now());
+    }
+}
+
+/// True once this hour's requests for `key` are used up. Uploads already under way
+/// still finish, so a budget can be overrun by the requests of those.
+pub fn is_exhausted(key: &str) -> bool {
+    BUDGETS.lock().unwrap().get_mut(key).is_some_and(|b| b.is_exhausted())
+}
+
+/// Writes the budget for `key` back, for the next run, and returns the minutes until
+/// a slot frees up.
+pub fn save(key: &str) -> Result<Option<i64>> {
+    match BUDGETS.lock().unwrap().get(key) {
+        Some(budget) => {
+            budget.save()?;
+            Ok(Some(budget.minutes_until_slot()))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Sliding one-hour window of server requests, persisted so that back-to-back
+/// (e.g. cron) runs share the same budget.
+struct RateBudget {
+    per_hour: u32,
+    sent: VecDeque<DateTime<Utc>>,
+}
+
+impl RateBudget {
+    fn load(per_hour: u32) -> Self {
+        let sent: VecDeque<DateTime<Utc>> = File::open(state::path(RATE_BUDGET_FILE))
+            .ok()
+            .and_then(|f| serde_json::from_reader(f).ok())
+            .unwrap_or_default();
+        let mut budget = Self { per_hour, sent };
+        budget.expire();
+        budget
+    }
+
+    fn save(&self) -> Result<()> {
+        serde_json::to_writer(File::create(state::path(RATE_BUDGET_FILE))?, &self.sent)?;
+        Ok(())
+    }
+
+    fn expire(&mut self) {
+        let cutoff = Utc::now() - Duration::hours(1);
+        while self.sent.front().is_some_and(|t| *t <= cutoff) {
+            self.sent.pop_front();
+        }
+    }
+
+    fn is_exhausted(&mut self) -> bool {
+        self.expire();
+        self.sent.len() >= self.per_hour as usize
+    }
+
+    /// Minutes until the oldest request in the window expires and frees a slot.
+    fn minutes_until_slot(&self) -> i64 {
+        self.sent
+            .front()
+            .map(|t| (*t + Duration::hours(1) - Utc::now()).num_minutes().max(0) + 1)
+            .unwrap_or(0)
+    }
+}
@@ -0,0 +1,61 @@
+use crate::api::get_asset;
+use crate::state;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::time::Duration;
+
+const RECEIPTS_FILE: &str = "immich_upload_receipts.json";
+
+/// An upload we haven't yet seen fully processed (thumbnail generated) on the server.
+#[derive(Serialize, Deserialize)]
+struct Receipt {
+    asset_id: String,
+    name: String,
+    uploaded_at: DateTime<Utc>,
+    #[serde(default)]
+    flagged: bool,
+}
+
+/// Records new uploads, then checks every outstanding receipt against the server and
+/// warns about assets still unprocessed after `grace`.
+pub async fn verify(client: &Client, base_url: &str, key: &str, uploaded: Vec<(String, String)>, grace: Duration) -> Result<()> {
+    let mut receipts: Vec<Receipt> = File::open(state::path(RECEIPTS_FILE))
+        .ok()
+        .and_then(|f| serde_json::from_reader(f).ok())
+        .unwrap_or_default();
+    let now = Utc::now();
+    receipts.extend(uploaded.into_iter().map(|(name, asset_id)| Receipt {
+        asset_id,
+        name,
+        uploaded_at: now,
+        flagged: false,
+    }));
+
+    let mut outstanding = Vec::new();
+    for mut receipt in receipts {
+        match get_asset(client, base_url, key, &receipt.asset_id).await? {
+            None => warn!("Uploaded asset for {} ({}) is missing on the server!", receipt.name, receipt.asset_id),
+            Some(asset) if asset.is_trashed => warn!("Uploaded asset for {} ({}) is in the trash.", receipt.name, receipt.asset_id),
+            Some(asset) if asset.thumbhash.is_some() => {
+                if receipt.flagged {
+                    info!("{} is now processed on the server.", receipt.name);
+                }
+            }
+            Some(_) => {
+                let age = (now - receipt.uploaded_at).to_std().unwrap_or_default();
+                if age >= grace && !receipt.flagged {
+                    warn!("{} is still unprocessed on the server after {} min.", receipt.name, age.as_secs() / 60);
+                    receipt.flagged = true;
+                }
+                outstanding.push(receipt);
+            }
+        }
+    }
+
+    serde_json::to_writer_pretty(File::create(state::path(RECEIPTS_FILE))?, &outstanding)?;
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use crate::album_cache::AlbumCache;
+use crate::api::find_all_by_checksum;
+use crate::config::Config;
+use crate::history::History;
+use crate::sync::{link_to_album, resolve_target};
+use anyhow::{Result, bail};
+use log::info;
+use reqwest::Client;
+
+// Files looked up per bulk-upload-check request
+const CHECK_BATCH: usize = 500;
+
+/// Finds assets that are in the upload history and on the server but not in the album
+/// (e.g. linking failed after the upload) and adds them. Files recorded before hashes
+/// were kept can't be looked up and are only counted.
+pub async fn run(client: &Client, config: &Config, dry_run: bool) -> Result<()> {
+    let target = resolve_target(client, config).await?;
+    let Some(album_id) = &target.album_id else {
+        bail!("reconcile checks one album; IMMICH_ALBUM_NAME is a template or unused");
+    };
+    let key = &config.api_key;
+
+    let history = History::open()?;
+    let mut entries = Vec::new();
+    for job in config.jobs() {
+        entries.extend(history.hashed(&job)?);
+    }
+    let hashed: Vec<(&str, &str)> = entries.iter().map(|(name, sha1)| (name.as_str(), sha1.as_str())).collect();
+    let unhashed = history.names()?.len() - hashed.len();
+
+    let mut cache = AlbumCache::load();
+    let members = cache.members(client, &target.base_url, key, album_id).await?.clone();
+    cache.save()?;
+    let mut orphans = Vec::new();
+    let mut missing = 0;
+    let mut trashed = 0;
+    for batch in hashed.chunks(CHECK_BATCH) {
+        let mut found = find_all_by_checksum(client, &target.base_url, key, batch).await?;
+        for (name, _) in batch {
+            match found.remove(*name) {
+                None => missing += 1,
+                Some(m) if m.is_trashed => trashed += 1,
+                Some(m) if !members.contains(&m.asset_id) => {
+                    info!("Not in the album: {}", name);
+                    orphans.push(m.asset_id);
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    info!(
+        "{} of {} uploaded files are missing from the album ({} no longer on the server, {} in the trash, {} without a hash)",
+        orphans.len(),
+        hashed.len() + unhashed,
+        missing,
+        trashed,
+        unhashed
+    );
+    if dry_run || orphans.is_empty() {
+        return Ok(());
+    }
+    link_to_album(client, &target.base_url, key, album_id, orphans).await
+}
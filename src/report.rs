@@ -0,0 +1,126 @@
+use crate::config::ReportFormat;
+use crate::summary::{Summary, escape_html};
+use anyhow::{Context, Result};
+use indicatif::HumanBytes;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Writes `report-<run id>.md` (or `.html`) to `dir`: the summary, then tables of the
+/// uploaded, skipped and failed files, linking to the assets on `server`. The HTML
+/// thumbnails load through the browser's Immich session, so the key isn't written out.
+pub fn write(dir: &Path, format: ReportFormat, server: &str, album: &str, summary: &Summary) -> Result<PathBuf> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let server = server.trim_end_matches('/');
+    let (extension, contents) = match format {
+        ReportFormat::Markdown => ("md", markdown(server, album, summary)),
+        ReportFormat::Html => ("html", html(server, album, summary)),
+    };
+    let path = dir.join(format!("report-{}.{}", summary.run_id, extension));
+    fs::write(&path, contents).with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+fn rows(summary: &Summary) -> [(&'static str, String); 8] {
+    [
+        ("Scanned", summary.scanned.to_string()),
+        ("In history", summary.in_history.to_string()),
+        ("Already on server", summary.duplicates.to_string()),
+        ("Uploaded", summary.uploaded.to_string()),
+        ("Failed", summary.failed.to_string()),
+        ("Skipped", summary.skipped.to_string()),
+        ("Transferred", HumanBytes(summary.bytes).to_string()),
+        ("Took", format!("{:.0}s", summary.elapsed.as_secs_f64())),
+    ]
+}
+
+fn markdown(server: &str, album: &str, summary: &Summary) -> String {
+    // Table cells end at a pipe or a line break
+    let cell = |text: &str| text.replace('|', "\\|").replace('\n', " ");
+    let link = |file: &str, id: &str| format!("[{}]({}/photos/{})", cell(file), server, id);
+
+    let mut out = format!("# Immich sync report, run {}\n\nServer: {}  \nAlbum: {}\n\n", summary.run_id, server, cell(album));
+    out.push_str("| | |\n|---|---|\n");
+    for (name, value) in rows(summary) {
+        let _ = writeln!(out, "| {} | {} |", name, value);
+    }
+    if !summary.uploads.is_empty() {
+        out.push_str("\n## Uploaded\n\n| File | Asset |\n|---|---|\n");
+        for (file, id) in &summary.uploads {
+            let _ = writeln!(out, "| {} | `{}` |", link(file, id), id);
+        }
+    }
+    if !summary.skips.is_empty() {
+        out.push_str("\n## Skipped\n\n| File | Reason |\n|---|---|\n");
+        for skip in &summary.skips {
+            let file = match &skip.asset_id {
+                Some(id) => link(&skip.file, id),
+                None => cell(&skip.file),
+            };
+            let _ = writeln!(out, "| {} | {} |", file, cell(&skip.reason));
+        }
+    }
+    if !summary.failures.is_empty() {
+        out.push_str("\n## Failed\n\n| File | Error |\n|---|---|\n");
+        for (file, error) in &summary.failures {
+            let _ = writeln!(out, "| {} | {} |", cell(file), cell(error));
+        }
+    }
+    out
+}
+
+fn html(server: &str, album: &str, summary: &Summary) -> String {
+    let server = escape_html(server);
+    let link = |file: &str, id: &str| {
+        format!(
+            "<a href=\"{}/photos/{}\"><img src=\"{}/api/assets/{}/thumbnail\" loading=\"lazy\" height=\"64\" alt=\"\"> {}</a>",
+            server,
+            escape_html(id),
+            server,
+            escape_html(id),
+            escape_html(file)
+        )
+    };
+    let table = "<table cellpadding=\"4\" border=\"1\" style=\"border-collapse: collapse\">";
+
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Immich sync report, run {}</title></head>\n\
+         <body style=\"font-family: sans-serif\">\n<h1>Immich sync report, run {}</h1>\n<p>Server: {}<br>Album: {}</p>\n",
+        summary.run_id,
+        summary.run_id,
+        server,
+        escape_html(album)
+    );
+    out.push_str("<table cellpadding=\"4\">\n");
+    for (name, value) in rows(summary) {
+        let _ = writeln!(out, "<tr><th align=\"left\">{}</th><td>{}</td></tr>", name, escape_html(&value));
+    }
+    out.push_str("</table>\n");
+    if !summary.uploads.is_empty() {
+        let _ = writeln!(out, "<h2>Uploaded</h2>\n{}\n<tr><th>File</th><th>Asset</th></tr>", table);
+        for (file, id) in &summary.uploads {
+            let _ = writeln!(out, "<tr><td>{}</td><td><code>{}</code></td></tr>", link(file, id), escape_html(id));
+        }
+        out.push_str("</table>\n");
+    }
+    if !summary.skips.is_empty() {
+        let _ = writeln!(out, "<h2>Skipped</h2>\n{}\n<tr><th>File</th><th>Reason</th></tr>", table);
+        for skip in &summary.skips {
+            let file = match &skip.asset_id {
+                Some(id) => link(&skip.file, id),
+                None => escape_html(&skip.file),
+            };
+            let _ = writeln!(out, "<tr><td>{}</td><td>{}</td></tr>", file, escape_html(&skip.reason));
+        }
+        out.push_str("</table>\n");
+    }
+    if !summary.failures.is_empty() {
+        let _ = writeln!(out, "<h2>Failed</h2>\n{}\n<tr><th>File</th><th>Error</th></tr>", table);
+        for (file, error) in &summary.failures {
+            let _ = writeln!(out, "<tr><td>{}</td><td>{}</td></tr>", escape_html(file), escape_html(error));
+        }
+        out.push_str("</table>\n");
+    }
+    out.push_str("</body></html>\n");
+    out
+}
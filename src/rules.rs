@@ -0,0 +1,185 @@
+use crate::config::Visibility;
+use crate::metadata::{camera, capture_time, read_exif};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDate};
+use exif::Exif;
+use serde::Deserialize;
+use std::cell::OnceCell;
+use std::fs;
+use std::path::Path;
+
+/// One `[[rule]]` of the rules file. All given conditions must hold for the actions
+/// to apply; a rule without conditions matches every file.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Rule {
+    /// Glob over the full path; `*` also matches `/`, e.g. `*/Screenshots/*`
+    path: Option<String>,
+    extension: Option<Vec<String>>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    /// Substring of the EXIF camera make and model
+    camera: Option<String>,
+    /// Capture date (EXIF, else modification time) bounds, inclusive
+    after: Option<toml::value::Datetime>,
+    before: Option<toml::value::Datetime>,
+
+    album: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    favorite: Option<bool>,
+    visibility: Option<Visibility>,
+    #[serde(default)]
+    skip: bool,
+}
+
+#[derive(Deserialize)]
+struct RulesFile {
+    #[serde(default, rename = "rule")]
+    rules: Vec<Rule>,
+}
+
+/// What the matching rules decided for a file.
+#[derive(Default)]
+pub struct Actions {
+    pub skip: bool,
+    /// Album to add the asset to instead of the configured one
+    pub album: Option<String>,
+    pub tags: Vec<String>,
+    pub favorite: bool,
+    pub visibility: Option<Visibility>,
+}
+
+/// Per-file routing rules (`IMMICH_RULES_FILE`). Every matching rule applies in file
+/// order: tags add up, the last `album`/`favorite`/`visibility` wins and `skip` wins outright.
+#[derive(Clone, Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("Failed to read rules file {}", path.display()))?;
+        let file: RulesFile = toml::from_str(&text).with_context(|| format!("Invalid rules file {}", path.display()))?;
+        Ok(Self { rules: file.rules })
+    }
+
+    pub fn evaluate(&self, path: &Path) -> Actions {
+        let mut actions = Actions::default();
+        let facts = Facts::new(path);
+        for rule in self.rules.iter().filter(|r| r.matches(&facts)) {
+            if rule.skip {
+                return Actions { skip: true, ..Actions::default() };
+            }
+            if rule.album.is_some() {
+                actions.album.clone_from(&rule.album);
+            }
+            if let Some(favorite) = rule.favorite {
+                actions.favorite = favorite;
+            }
+            if rule.visibility.is_some() {
+                actions.visibility = rule.visibility;
+            }
+            for tag in &rule.tags {
+                if !actions.tags.contains(tag) {
+                    actions.tags.push(tag.clone());
+                }
+            }
+        }
+        actions
+    }
+}
+
+impl Rule {
+    fn matches(&self, facts: &Facts) -> bool {
+        if let Some(pattern) = &self.path
+            && !wildcard(pattern.as_bytes(), facts.path.to_string_lossy().replace('\\', "/").as_bytes())
+        {
+            return false;
+        }
+        if let Some(extensions) = &self.extension {
+            let ext = facts.path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+            if !extensions.iter().any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(&ext)) {
+                return false;
+            }
+        }
+        if self.min_size.is_some_and(|min| facts.size() < min) || self.max_size.is_some_and(|max| facts.size() > max) {
+            return false;
+        }
+        if let Some(wanted) = &self.camera {
+            let wanted = wanted.to_lowercase();
+            if !facts.exif().and_then(camera).is_some_and(|c| c.to_lowercase().contains(&wanted)) {
+                return false;
+            }
+        }
+        if self.after.is_some() || self.before.is_some() {
+            let Some(date) = facts.date() else {
+                return false;
+            };
+            if self.after.and_then(to_date).is_some_and(|after| date < after)
+                || self.before.and_then(to_date).is_some_and(|before| date > before)
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// File properties, looked up only when a rule asks for them.
+struct Facts<'a> {
+    path: &'a Path,
+    size: OnceCell<u64>,
+    exif: OnceCell<Option<Exif>>,
+}
+
+impl<'a> Facts<'a> {
+    fn new(path: &'a Path) -> Self {
+        Self { path, size: OnceCell::new(), exif: OnceCell::new() }
+    }
+
+    fn size(&self) -> u64 {
+        *self.size.get_or_init(|| self.path.metadata().map(|m| m.len()).unwrap_or(0))
+    }
+
+    fn exif(&self) -> Option<&Exif> {
+        self.exif.get_or_init(|| read_exif(self.path)).as_ref()
+    }
+
+    fn date(&self) -> Option<NaiveDate> {
+        match self.exif().and_then(capture_time) {
+            Some(taken) => Some(taken.date_naive()),
+            None => {
+                let modified = self.path.metadata().and_then(|m| m.modified()).ok()?;
+                Some(DateTime::<Local>::from(modified).date_naive())
+            }
+        }
+    }
+}
+
+fn to_date(value: toml::value::Datetime) -> Option<NaiveDate> {
+    let date = value.date?;
+    NaiveDate::from_ymd_opt(date.year.into(), date.month.into(), date.day.into())
+}
+
+/// Matches `*` (any run of characters) and `?` (one character).
+pub fn wildcard(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
@@ -0,0 +1,23 @@
+use chrono::Local;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::RwLock;
+
+/// Sent with every server request so proxy/server logs can be matched to a run.
+pub const RUN_ID_HEADER: &str = "X-Client-Run-Id";
+
+// Passes never overlap, so one process-wide ID is enough.
+static CURRENT: RwLock<String> = RwLock::new(String::new());
+
+/// Starts a new run (one sync pass) and returns its ID, e.g. `20240814-103312-3fa9c2`.
+pub fn start() -> String {
+    let salt = RandomState::new().hash_one(std::process::id());
+    let id = format!("{}-{:06x}", Local::now().format("%Y%m%d-%H%M%S"), salt & 0xff_ffff);
+    *CURRENT.write().unwrap() = id.clone();
+    id
+}
+
+/// The ID of the run in progress, or empty outside a run (e.g. one-off subcommands).
+pub fn current() -> String {
+    CURRENT.read().unwrap().clone()
+}
@@ -0,0 +1,249 @@
+use crate::config::{Config, DateRange, UploadOrder};
+use chrono::{DateTime, Datelike, Local, NaiveDate};
+use jwalk::WalkDir;
+use log::{debug, warn};
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::mpsc;
+use tracing::{field, info_span};
+
+/// Scans the configured folders (or just `only`, when given) on a background thread
+/// pool and streams the uploadable files back. With `UploadOrder::Scan` each file is
+/// sent as soon as it is found; the other orders need a folder's full list before
+/// sorting it. Files from several folders are interleaved by weight.
+pub fn spawn_scan(config: &Config, only: Option<Vec<PathBuf>>) -> mpsc::Receiver<PathBuf> {
+    let (tx, rx) = mpsc::channel(1024);
+    let folders = config.folders.clone();
+    let (recursive, date_range, order) = (config.recursive, config.date_range, config.order);
+    let excluded = Arc::new(config.excluded.clone());
+
+    let span = info_span!("scan", files = field::Empty);
+    tokio::task::spawn_blocking(move || {
+        let _entered = span.enter();
+        let accept = move |p: &PathBuf| is_supported(p) && in_date_range(p, &date_range) && !excluded.iter().any(|e| p.starts_with(e));
+        let files: Box<dyn Iterator<Item = PathBuf>> = match only {
+            Some(paths) => Box::new(paths.into_iter().filter(|p| p.is_file()).filter(accept)),
+            None => {
+                let sources = folders
+                    .into_iter()
+                    .filter(|f| f.path.is_dir())
+                    .map(|f| {
+                        let files = walk(&f.path, recursive, date_range).filter(accept.clone());
+                        let files: Box<dyn Iterator<Item = PathBuf>> = if matches!(order, UploadOrder::Scan) {
+                            Box::new(files)
+                        } else {
+                            let mut entries: Vec<PathBuf> = files.collect();
+                            sort_entries(&mut entries, order);
+                            Box::new(entries.into_iter())
+                        };
+                        (files, f.weight)
+                    })
+                    .collect();
+                Box::new(interleave(sources))
+            }
+        };
+
+        let mut sent = 0;
+        for path in files {
+            if tx.blocking_send(path).is_err() {
+                break;
+            }
+            sent += 1;
+        }
+        span.record("files", sent);
+    });
+    rx
+}
+
+/// Weighted round-robin over the per-folder file lists: `weight` files from each
+/// folder in turn, so a big backlog in one folder can't starve the others.
+fn interleave(mut sources: Vec<(Box<dyn Iterator<Item = PathBuf>>, usize)>) -> impl Iterator<Item = PathBuf> {
+    let (mut current, mut taken) = (0, 0);
+    std::iter::from_fn(move || {
+        while !sources.is_empty() {
+            current %= sources.len();
+            let (files, weight) = &mut sources[current];
+            if taken < *weight {
+                if let Some(path) = files.next() {
+                    taken += 1;
+                    return Some(path);
+                }
+                drop(sources.remove(current));
+            } else {
+                current += 1;
+            }
+            taken = 0;
+        }
+        None
+    })
+}
+
+/// Parallel directory walk that never descends into dated folders outside `date_range`.
+fn walk(root: &Path, recursive: bool, date_range: DateRange) -> impl Iterator<Item = PathBuf> + use<> {
+    WalkDir::new(root)
+        .skip_hidden(false)
+        .max_depth(if recursive { usize::MAX } else { 1 })
+        .process_read_dir(move |_, parent, _, children| {
+            let parent_year = parent
+                .file_name()
+                .and_then(|n| dir_date_span(&n.to_string_lossy(), None))
+                .map(|(start, _)| start.year());
+            for entry in children.iter_mut().flatten() {
+                if !entry.file_type.is_dir() {
+                    continue;
+                }
+                if let Some((start, end)) = dir_date_span(&entry.file_name.to_string_lossy(), parent_year)
+                    && !date_range.overlaps(start, end)
+                {
+                    debug!("Skipping {} (outside date range)", entry.path().display());
+                    entry.read_children_path = None;
+                }
+            }
+        })
+        .into_iter()
+        .filter_map(|res| match res {
+            Ok(entry) => entry.file_type.is_file().then(|| entry.path()),
+            Err(e) => {
+                warn!("Scan error: {}", e);
+                None
+            }
+        })
+}
+
+/// Recognises date-structured folder names: `2021`, `2021-07`, or `07` inside a year folder.
+fn dir_date_span(name: &str, parent_year: Option<i32>) -> Option<(NaiveDate, NaiveDate)> {
+    let month_span = |y: i32, m: u32| {
+        let start = NaiveDate::from_ymd_opt(y, m, 1)?;
+        let next = if m == 12 { NaiveDate::from_ymd_opt(y + 1, 1, 1)? } else { NaiveDate::from_ymd_opt(y, m + 1, 1)? };
+        Some((start, next.pred_opt()?))
+    };
+    let all_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+
+    if name.len() == 4 && all_digits(name) {
+        let y: i32 = name.parse().ok()?;
+        if (1900..=2100).contains(&y) {
+            return Some((NaiveDate::from_ymd_opt(y, 1, 1)?, NaiveDate::from_ymd_opt(y, 12, 31)?));
+        }
+    } else if let Some((y, m)) = name.split_once('-')
+        && y.len() == 4
+        && m.len() == 2
+        && all_digits(y)
+        && all_digits(m)
+    {
+        return month_span(y.parse().ok()?, m.parse().ok()?);
+    } else if name.len() == 2 && all_digits(name) {
+        return month_span(parent_year?, name.parse().ok()?);
+    }
+    None
+}
+
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "webp", "heic", "heif", "avif"];
+pub const RAW_EXTENSIONS: &[&str] = &["cr2", "cr3", "nef", "arw", "dng", "orf", "raf", "rw2"];
+pub const VIDEO_EXTENSIONS: &[&str] = &["mp4", "mov", "mkv", "webm", "m4v", "3gp"];
+
+fn is_supported(path: &Path) -> bool {
+    if let Some(ext) = path.extension() {
+        let s = ext.to_string_lossy().to_lowercase();
+        [IMAGE_EXTENSIONS, RAW_EXTENSIONS, VIDEO_EXTENSIONS].iter().any(|list| list.contains(&s.as_str()))
+    } else {
+        false
+    }
+}
+
+/// Finds an XMP sidecar next to `path`: `photo.cr3.xmp` (darktable) or `photo.xmp` (Lightroom).
+pub fn sidecar_for(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_string_lossy().to_string();
+    let stem = path.file_stem()?.to_string_lossy().to_string();
+    [name, stem]
+        .iter()
+        .flat_map(|base| [format!("{}.xmp", base), format!("{}.XMP", base)])
+        .map(|candidate| path.with_file_name(candidate))
+        .find(|candidate| candidate.is_file())
+}
+
+const LIVE_STILL_EXTENSIONS: &[&str] = &["heic", "heif", "jpg", "jpeg"];
+const LIVE_VIDEO_EXTENSIONS: &[&str] = &["mov", "mp4"];
+
+/// The motion half of an iPhone Live Photo: `IMG_1234.MOV` next to `IMG_1234.HEIC`.
+pub fn live_photo_video_for(still: &Path) -> Option<PathBuf> {
+    has_extension(still, LIVE_STILL_EXTENSIONS).then(|| sibling_with(still, LIVE_VIDEO_EXTENSIONS)).flatten()
+}
+
+/// The still a Live Photo video belongs to, if any; the pair is uploaded together from it.
+pub fn live_photo_still_for(video: &Path) -> Option<PathBuf> {
+    has_extension(video, LIVE_VIDEO_EXTENSIONS).then(|| sibling_with(video, LIVE_STILL_EXTENSIONS)).flatten()
+}
+
+/// The camera JPEG shot alongside a RAW file: `IMG_0042.JPG` next to `IMG_0042.CR3`.
+pub fn jpeg_for_raw(raw: &Path) -> Option<PathBuf> {
+    has_extension(raw, RAW_EXTENSIONS).then(|| sibling_with(raw, &["jpg", "jpeg"])).flatten()
+}
+
+pub fn has_extension(path: &Path, list: &[&str]) -> bool {
+    path.extension().is_some_and(|e| list.contains(&e.to_string_lossy().to_lowercase().as_str()))
+}
+
+fn sibling_with(path: &Path, extensions: &[&str]) -> Option<PathBuf> {
+    extensions
+        .iter()
+        .flat_map(|ext| [ext.to_string(), ext.to_uppercase()])
+        .map(|ext| path.with_extension(ext))
+        .find(|candidate| candidate.is_file())
+}
+
+fn in_date_range(path: &Path, range: &DateRange) -> bool {
+    if range.is_unbounded() {
+        return true;
+    }
+    match path.metadata().and_then(|m| m.modified()) {
+        Ok(modified) => range.contains(DateTime::<Local>::from(modified).date_naive()),
+        Err(_) => true,
+    }
+}
+
+fn sort_entries(entries: &mut [PathBuf], order: UploadOrder) {
+    let mtime = |p: &PathBuf| p.metadata().ok().and_then(|m| m.modified().ok()).unwrap_or(SystemTime::UNIX_EPOCH);
+    match order {
+        UploadOrder::Oldest => entries.sort_by_cached_key(mtime),
+        UploadOrder::Newest => entries.sort_by_cached_key(|p| std::cmp::Reverse(mtime(p))),
+        UploadOrder::Name => entries.sort_by_cached_key(|p| p.file_name().map(|n| n.to_os_string())),
+        UploadOrder::Size => entries.sort_by_cached_key(|p| p.metadata().map(|m| m.len()).unwrap_or(0)),
+        UploadOrder::Random => {
+            // A freshly seeded hasher gives a different shuffle on every run
+            let state = RandomState::new();
+            entries.sort_by_cached_key(|p| state.hash_one(p));
+        }
+        UploadOrder::Scan => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(prefix: &str, count: usize, weight: usize) -> (Box<dyn Iterator<Item = PathBuf>>, usize) {
+        let files: Vec<PathBuf> = (1..=count).map(|n| PathBuf::from(format!("{}{}", prefix, n))).collect();
+        (Box::new(files.into_iter()), weight)
+    }
+
+    fn order(sources: Vec<(Box<dyn Iterator<Item = PathBuf>>, usize)>) -> Vec<String> {
+        interleave(sources).map(|p| p.to_string_lossy().to_string()).collect()
+    }
+
+    #[test]
+    fn interleaves_by_weight() {
+        let sources = vec![source("a", 6, 3), source("b", 2, 1)];
+        assert_eq!(order(sources), ["a1", "a2", "a3", "b1", "a4", "a5", "a6", "b2"]);
+    }
+
+    #[test]
+    fn interleaves_sources_that_run_out_early() {
+        // `c` is done after one turn, `a` in the middle of its second; `b` goes on alone
+        let sources = vec![source("a", 3, 2), source("b", 5, 1), source("c", 1, 1)];
+        assert_eq!(order(sources), ["a1", "a2", "b1", "c1", "a3", "b2", "b3", "b4", "b5"]);
+        assert!(order(vec![source("a", 0, 2), source("b", 0, 1)]).is_empty());
+    }
+}
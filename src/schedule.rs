@@ -0,0 +1,90 @@
+use crate::config::Config;
+use crate::network::is_metered;
+use anyhow::{Context, Result, bail};
+use chrono::{Local, NaiveTime};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Daily time range (local time) in which uploads are allowed, e.g. `22:00-06:00`.
+#[derive(Clone, Copy)]
+pub struct UploadWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl UploadWindow {
+    pub fn is_open(&self) -> bool {
+        self.contains(Local::now().time())
+    }
+
+    fn contains(&self, t: NaiveTime) -> bool {
+        if self.start == self.end {
+            true
+        } else if self.start < self.end {
+            t >= self.start && t < self.end
+        } else {
+            // Wraps past midnight
+            t >= self.start || t < self.end
+        }
+    }
+
+    /// Time left until the window next opens (zero if it is open now).
+    pub fn until_open(&self) -> Duration {
+        let now = Local::now().time();
+        if self.contains(now) {
+            return Duration::ZERO;
+        }
+        let wait = (self.start - now).num_seconds().rem_euclid(24 * 3600);
+        Duration::from_secs(wait as u64)
+    }
+}
+
+impl FromStr for UploadWindow {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((start, end)) = s.split_once('-') else {
+            bail!("expected HH:MM-HH:MM, got '{}'", s);
+        };
+        let parse = |t: &str| NaiveTime::parse_from_str(t.trim(), "%H:%M").with_context(|| format!("invalid time '{}'", t));
+        Ok(Self { start: parse(start)?, end: parse(end)? })
+    }
+}
+
+impl std::fmt::Display for UploadWindow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.start.format("%H:%M"), self.end.format("%H:%M"))
+    }
+}
+
+/// Why uploads shouldn't run right now, if anything is holding them back.
+pub async fn blocked_reason(config: &Config) -> Option<String> {
+    Gate::of(config).blocked_reason().await
+}
+
+/// What decides whether uploads may run, apart from the config, so upload tasks can
+/// check it again when their turn comes.
+#[derive(Clone, Copy)]
+pub struct Gate {
+    window: Option<UploadWindow>,
+    pause_on_metered: bool,
+}
+
+impl Gate {
+    pub fn of(config: &Config) -> Self {
+        Self { window: config.upload_window, pause_on_metered: config.pause_on_metered }
+    }
+
+    /// See `blocked_reason`.
+    pub async fn blocked_reason(self) -> Option<String> {
+        if let Some(window) = self.window
+            && !window.is_open()
+        {
+            return Some(format!("Outside upload window ({})", window));
+        }
+        if self.pause_on_metered && is_metered().await == Some(true) {
+            return Some("On a metered connection".to_string());
+        }
+        None
+    }
+}
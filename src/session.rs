@@ -0,0 +1,102 @@
+use crate::compat;
+use crate::state;
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Utc};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufRead, Write};
+use std::sync::{LazyLock, Mutex};
+
+const SESSION_FILE: &str = "immich_session.json";
+/// Immich accepts this redirect for its mobile app; the browser can't open it, which
+/// leaves the final URL (with the code) in the address bar to copy.
+const OAUTH_REDIRECT: &str = "app.immich:///oauth-callback";
+
+/// A login session used instead of `IMMICH_API_KEY`. Immich keeps sessions alive as
+/// long as they're used; once one is revoked, `validate_key` asks for a new login.
+#[derive(Serialize, Deserialize)]
+struct Session {
+    server: String,
+    email: String,
+    access_token: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LoginResponse {
+    access_token: String,
+    #[serde(default)]
+    user_email: String,
+}
+
+#[derive(Deserialize)]
+struct AuthorizeResponse {
+    url: String,
+}
+
+// Tokens that go in an `Authorization: Bearer` header rather than `x-api-key`
+static TOKENS: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(Default::default);
+
+pub fn is_session_token(key: &str) -> bool {
+    TOKENS.lock().unwrap().contains(key)
+}
+
+/// The saved session token, if `login` was run.
+pub fn load() -> Option<String> {
+    let session: Session = serde_json::from_reader(File::open(state::root().join(SESSION_FILE)).ok()?).ok()?;
+    TOKENS.lock().unwrap().insert(session.access_token.clone());
+    Some(session.access_token)
+}
+
+/// `login`: signs in through the server's OAuth provider, or with email and password
+/// (read from stdin) when `email` is given, and saves the session.
+pub async fn login(client: &Client, base_url: &str, email: Option<&str>) -> Result<()> {
+    let resp = match email {
+        Some(email) => {
+            eprint!("Password for {}: ", email);
+            io::stderr().flush()?;
+            let mut password = String::new();
+            io::stdin().lock().read_line(&mut password)?;
+            let body = serde_json::json!({ "email": email, "password": password.trim_end_matches(['\r', '\n']) });
+            client.post(compat::url(base_url, "/api/auth/login")).json(&body).send().await?
+        }
+        None => {
+            let body = serde_json::json!({ "redirectUri": OAUTH_REDIRECT });
+            let resp = client.post(compat::url(base_url, "/api/oauth/authorize")).json(&body).send().await?;
+            let authorize: AuthorizeResponse = resp.error_for_status().context("The server doesn't offer OAuth login")?.json().await?;
+            eprintln!("Open this link, sign in, then paste the address the browser ends up on:\n\n{}\n", authorize.url);
+            eprint!("> ");
+            io::stderr().flush()?;
+            let mut redirected = String::new();
+            io::stdin().lock().read_line(&mut redirected)?;
+            let body = serde_json::json!({ "url": redirected.trim() });
+            client.post(compat::url(base_url, "/api/oauth/callback")).json(&body).send().await?
+        }
+    };
+    if !resp.status().is_success() {
+        bail!("Login failed: {} {}", resp.status(), resp.text().await.unwrap_or_default());
+    }
+    let login: LoginResponse = resp.json().await?;
+    let session = Session {
+        server: base_url.to_string(),
+        email: login.user_email,
+        access_token: login.access_token,
+        created_at: Utc::now(),
+    };
+    save(&session)?;
+    println!("Logged in as {} on {}; IMMICH_API_KEY is no longer needed.", session.email, session.server);
+    Ok(())
+}
+
+// The token is as good as a password: readable by the owner only
+fn save(session: &Session) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    serde_json::to_writer_pretty(options.open(state::root().join(SESSION_FILE))?, session)?;
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use crate::compat;
+use anyhow::{Result, bail};
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::env;
+use std::sync::{LazyLock, Mutex};
+
+/// Header Immich reads shared link keys from.
+pub const SHARE_KEY_HEADER: &str = "x-immich-share-key";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SharedLink {
+    #[serde(default)]
+    allow_upload: bool,
+    album: Option<LinkedAlbum>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LinkedAlbum {
+    id: String,
+    album_name: String,
+}
+
+// Keys that go in `SHARE_KEY_HEADER` rather than `x-api-key`
+static KEYS: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(Default::default);
+
+pub fn is_share_key(key: &str) -> bool {
+    KEYS.lock().unwrap().contains(key)
+}
+
+/// `IMMICH_SHARE_KEY`: the key of an "allow upload" album shared link, or the link
+/// itself (`https://photos.example.org/share/<key>`). Returns the key and, for a link,
+/// the server it points at.
+pub fn from_env() -> Option<(String, Option<String>)> {
+    let value = env::var("IMMICH_SHARE_KEY").ok().filter(|v| !v.is_empty())?;
+    let (key, server) = match value.split_once("/share/") {
+        Some((server, rest)) => (rest.split(['/', '?', '#']).next().unwrap_or_default().to_string(), Some(server.to_string())),
+        None => (value, None),
+    };
+    KEYS.lock().unwrap().insert(key.clone());
+    Some((key, server))
+}
+
+/// The album behind the shared link, after checking that it takes uploads.
+pub async fn album_id(client: &Client, base_url: &str, key: &str) -> Result<String> {
+    let resp = client
+        .get(compat::url(base_url, "/api/shared-links/me"))
+        .header(SHARE_KEY_HEADER, key)
+        .send()
+        .await?;
+    if resp.status() == StatusCode::UNAUTHORIZED || resp.status() == StatusCode::FORBIDDEN {
+        bail!("The shared link key for {} is invalid, or the link has expired (server said {})", base_url, resp.status());
+    }
+    let link: SharedLink = resp.error_for_status()?.json().await?;
+    let Some(album) = link.album else {
+        bail!("The shared link is not for an album; only album links can be uploaded to");
+    };
+    if !link.allow_upload {
+        bail!("The shared link for '{}' doesn't allow uploads", album.album_name);
+    }
+    Ok(album.id)
+}
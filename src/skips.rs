@@ -0,0 +1,81 @@
+use crate::state;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+
+const SKIPS_FILE: &str = "immich_skips.json";
+// Oldest decisions are dropped beyond this, so the file stays small in daemon mode.
+const MAX_SKIPS: usize = 5000;
+
+/// Why a file wasn't uploaded, as last decided.
+#[derive(Serialize, Deserialize)]
+pub struct Skip {
+    pub reason: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    /// Passes that made the same decision
+    pub count: u32,
+}
+
+/// The latest skip decision per file (by `Config::file_key`), for auditing with `skips
+/// show`. Repeating a decision (every pass skips files already in the history) only
+/// bumps its count.
+#[derive(Default, Serialize, Deserialize)]
+pub struct SkipLog {
+    files: BTreeMap<String, Skip>,
+}
+
+impl SkipLog {
+    pub fn load() -> Self {
+        let mut skips: Self = File::open(state::path(SKIPS_FILE))
+            .ok()
+            .and_then(|f| serde_json::from_reader(f).ok())
+            .unwrap_or_default();
+        // Entries by bare name may mix up same-named files; the next pass records them anew
+        skips.files.retain(|key, _| key.contains('|'));
+        skips
+    }
+
+    pub fn save(&mut self) -> Result<()> {
+        if self.files.len() > MAX_SKIPS {
+            let mut seen: Vec<DateTime<Utc>> = self.files.values().map(|s| s.last_seen).collect();
+            seen.sort_unstable();
+            let cutoff = seen[seen.len() - MAX_SKIPS];
+            self.files.retain(|_, s| s.last_seen >= cutoff);
+        }
+        serde_json::to_writer_pretty(File::create(state::path(SKIPS_FILE))?, self)?;
+        Ok(())
+    }
+
+    pub fn record(&mut self, key: &str, reason: impl Into<String>) {
+        let reason = reason.into();
+        let now = Utc::now();
+        match self.files.get_mut(key) {
+            Some(skip) if skip.reason == reason => {
+                skip.last_seen = now;
+                skip.count += 1;
+            }
+            _ => {
+                self.files.insert(key.to_string(), Skip { reason, first_seen: now, last_seen: now, count: 1 });
+            }
+        }
+    }
+
+    /// Like `record`, but keeps an earlier reason: a file skipped as a duplicate is in the
+    /// history afterwards, and "in the history" would hide why it got there.
+    pub fn record_again(&mut self, key: &str, reason: &str) {
+        match self.files.get_mut(key) {
+            Some(skip) => {
+                skip.last_seen = Utc::now();
+                skip.count += 1;
+            }
+            None => self.record(key, reason),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Skip)> {
+        self.files.iter()
+    }
+}
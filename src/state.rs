@@ -0,0 +1,102 @@
+use anyhow::Result;
+use directories::ProjectDirs;
+use std::env;
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+/// Mirror servers keep their state below this, one folder each.
+pub const MIRRORS_DIR: &str = "immich_mirrors";
+/// Likewise for the accounts of `IMMICH_USER_<n>_*`.
+pub const USERS_DIR: &str = "immich_users";
+
+/// What earlier versions created in the working directory, moved to `root()` by `init`.
+const LEGACY_FILES: [&str; 17] = [
+    "immich_state.db",
+    "immich_state.db-wal",
+    "immich_state.db-shm",
+    "immich_state.db.bak",
+    "immich_upload_history.json",
+    "immich_upload_history.json.migrated",
+    "immich_health.json",
+    "immich_skips.json",
+    "immich_dead_letters.json",
+    "immich_album_cache.json",
+    "immich_rate_budget.json",
+    "immich_upload_receipts.json",
+    "immich_session.json",
+    "immich_archive_manifest.jsonl",
+    "immich_backup.log",
+    MIRRORS_DIR,
+    USERS_DIR,
+];
+
+static DIRS: LazyLock<Option<ProjectDirs>> = LazyLock::new(|| ProjectDirs::from("", "", "immich_sync"));
+
+/// `IMMICH_STATE_DIR`, else the platform's place for it: `$XDG_STATE_HOME/immich_sync` on
+/// Linux, the app's local data folder elsewhere. Run from cron or a shell, it's the same.
+static ROOT: LazyLock<PathBuf> = LazyLock::new(|| match env::var_os("IMMICH_STATE_DIR") {
+    Some(dir) => dir.into(),
+    None => DIRS
+        .as_ref()
+        .map(|d| d.state_dir().unwrap_or(d.data_local_dir()).to_path_buf())
+        .unwrap_or_else(|| PathBuf::from(".")),
+});
+
+/// Where the history, caches, session and log live (before any mirror or user folder).
+pub fn root() -> &'static Path {
+    &ROOT
+}
+
+/// `IMMICH_CONFIG_DIR`, else the platform's config folder (`$XDG_CONFIG_HOME/immich_sync`),
+/// which may hold a `.env` and `mappings.toml`.
+pub fn config_dir() -> Option<PathBuf> {
+    match env::var_os("IMMICH_CONFIG_DIR") {
+        Some(dir) => Some(dir.into()),
+        None => DIRS.as_ref().map(|d| d.config_dir().to_path_buf()),
+    }
+}
+
+/// Creates `root()` and moves state files left in the working directory by older
+/// versions into it. Returns what was moved.
+pub fn init() -> Result<Vec<String>> {
+    fs::create_dir_all(root())?;
+    if fs::canonicalize(root())? == env::current_dir()?.canonicalize()? {
+        return Ok(Vec::new());
+    }
+    let mut moved = Vec::new();
+    for name in LEGACY_FILES {
+        let (old, new) = (Path::new(name), root().join(name));
+        if old.exists() && !new.exists() {
+            // A rename can't cross file systems; better left in place than half copied
+            match fs::rename(old, &new) {
+                Ok(()) => moved.push(name.to_string()),
+                Err(e) => eprintln!("Could not move {} to {}: {}", name, root().display(), e),
+            }
+        }
+    }
+    Ok(moved)
+}
+
+tokio::task_local! {
+    static NAMESPACE: PathBuf;
+}
+
+/// Where a state file lives: in `root()`, or in the mirror's (or user's) own folder while
+/// their pass runs (see `scoped`), so each has its own history, caches and health.
+pub fn path(file: impl AsRef<Path>) -> PathBuf {
+    let file = file.as_ref();
+    match NAMESPACE.try_with(|dir| dir.clone()) {
+        Ok(dir) => dir.join(file.file_name().unwrap_or(file.as_os_str())),
+        Err(_) => root().join(file),
+    }
+}
+
+/// Runs `f` with state files in `dir`, e.g. `MIRRORS_DIR/<namespace>`. Only the calling
+/// task sees it, so state must not be touched from tasks `f` spawns.
+pub async fn scoped<F: Future>(dir: PathBuf, f: F) -> Result<F::Output> {
+    let dir = root().join(dir);
+    fs::create_dir_all(&dir)?;
+    Ok(NAMESPACE.scope(dir, f).await)
+}
@@ -0,0 +1,82 @@
+use crate::config::Config;
+use crate::dead_letter::DeadLetters;
+use crate::health::Health;
+use crate::history::History;
+use crate::scan::spawn_scan;
+use crate::skips::SkipLog;
+use anyhow::Result;
+use chrono::{Datelike, Days, Local, NaiveDate};
+use indicatif::HumanBytes;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+
+const DAYS_SHOWN: u64 = 7;
+const WEEKS_SHOWN: u64 = 8;
+
+/// Prints totals from the state store: what the history tracks, recent uploads per day
+/// and week, failures, and the `top` largest files still waiting. Read-only; the only
+/// work beyond the state files is scanning the folders for pending files.
+pub async fn run(config: &Config, top: usize) -> Result<()> {
+    let records = History::open()?.records()?;
+    let bytes: u64 = records.iter().filter_map(|r| r.size).sum();
+    let without_size = records.iter().filter(|r| r.size.is_none()).count();
+    println!("Files tracked:     {}", records.len());
+    print!("Bytes uploaded:    {}", HumanBytes(bytes));
+    if without_size > 0 {
+        print!(" (plus {} older entries without a size)", without_size);
+    }
+    println!();
+    if let Some(first) = records.iter().map(|r| r.uploaded_at).min() {
+        println!("First upload:      {}", first.with_timezone(&Local).format("%Y-%m-%d"));
+    }
+
+    let today = Local::now().date_naive();
+    let mut per_day: BTreeMap<NaiveDate, usize> = BTreeMap::new();
+    for record in &records {
+        *per_day.entry(record.uploaded_at.with_timezone(&Local).date_naive()).or_default() += 1;
+    }
+    println!("\nUploads per day:");
+    for ago in (0..DAYS_SHOWN).rev() {
+        let day = today - Days::new(ago);
+        println!("  {}  {}", day.format("%a %Y-%m-%d"), per_day.get(&day).copied().unwrap_or(0));
+    }
+    // Weeks start on Monday, the current one included however far it has got
+    let this_week = today - Days::new(today.weekday().num_days_from_monday().into());
+    println!("\nUploads per week:");
+    for ago in (0..WEEKS_SHOWN).rev() {
+        let start = this_week - Days::new(ago * 7);
+        let count: usize = per_day.range(start..start + Days::new(7)).map(|(_, n)| n).sum();
+        println!("  week of {}  {}", start.format("%Y-%m-%d"), count);
+    }
+
+    let dead_letters = DeadLetters::load();
+    let failing = dead_letters.iter().count();
+    let attempts: u32 = dead_letters.iter().map(|(_, f)| f.attempts).sum();
+    let dead = dead_letters.iter().filter(|(name, _)| dead_letters.is_dead(name, config.max_attempts)).count();
+    let health = Health::load();
+    println!("\nFailures:");
+    println!("  Files with failed uploads: {} ({} failed attempts, {} dead letters)", failing, attempts, dead);
+    println!("  Files skipped (see `skips show`): {}", SkipLog::load().iter().count());
+    println!("  Passes failed in a row: {}", health.consecutive_failures);
+    if let Some(error) = &health.last_error {
+        println!("  Last error: {}", error);
+    }
+
+    // Pending by name, as `diff` sees it; the sync itself also compares checksums
+    let tracked: HashSet<&str> = records.iter().map(|r| r.name.as_str()).collect();
+    let mut pending: Vec<(u64, PathBuf)> = Vec::new();
+    let mut scan = spawn_scan(config, None);
+    while let Some(path) = scan.recv().await {
+        if !tracked.contains(path.file_name().unwrap().to_string_lossy().as_ref()) {
+            pending.push((fs::metadata(&path).map(|m| m.len()).unwrap_or(0), path));
+        }
+    }
+    pending.sort_by_key(|(size, _)| std::cmp::Reverse(*size));
+    let pending_bytes: u64 = pending.iter().map(|(size, _)| size).sum();
+    println!("\nPending: {} file(s), {}", pending.len(), HumanBytes(pending_bytes));
+    for (size, path) in pending.iter().take(top) {
+        println!("  {:>10}  {}", HumanBytes(*size).to_string(), path.display());
+    }
+    Ok(())
+}
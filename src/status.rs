@@ -0,0 +1,268 @@
+use crate::health::DEGRADED_AFTER;
+use crate::progress;
+use crate::summary::Summary;
+use indicatif::{HumanBytes, HumanDuration, ProgressBar};
+use log::{info, warn};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+const MAX_RECENT_ERRORS: usize = 20;
+/// The current rate is measured over this much of the recent past.
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Live view of what the uploader is doing, shared between the sync pass and the daemon.
+#[derive(Default)]
+pub struct Status {
+    inner: Mutex<StatusInner>,
+    paused: AtomicBool,
+    resumed: Notify,
+}
+
+#[derive(Default)]
+struct StatusInner {
+    queue: VecDeque<String>,
+    active: BTreeMap<String, (u64, u64)>,
+    bars: HashMap<String, ProgressBar>,
+    errors: VecDeque<String>,
+    consecutive_failures: u32,
+    last_failure: Option<String>,
+    transfer: Transfer,
+    totals: Totals,
+    last_summary: Option<Summary>,
+}
+
+/// Counts since the process started, for the metrics endpoint.
+#[derive(Clone, Default)]
+pub struct Totals {
+    pub uploads: u64,
+    pub failures: u64,
+    pub bytes: u64,
+}
+
+impl StatusInner {
+    fn throughput(&self) -> Option<Throughput> {
+        let transfer = &self.transfer;
+        let elapsed = transfer.started?.elapsed().as_secs_f64().max(1.0);
+        let window = elapsed.min(RATE_WINDOW.as_secs_f64());
+        let in_flight: u64 = self.active.values().map(|(sent, _)| sent).sum();
+        Some(Throughput {
+            current: (transfer.recent.iter().map(|(_, b)| b).sum::<u64>() as f64 / window) as u64,
+            average: (transfer.sent as f64 / elapsed) as u64,
+            remaining: transfer.queued.saturating_sub(transfer.finished + in_flight),
+        })
+    }
+}
+
+/// Bytes moved during the current pass.
+#[derive(Default)]
+struct Transfer {
+    /// When the first byte went out
+    started: Option<Instant>,
+    /// Size of every file queued for upload, and of those done with (uploaded or not)
+    queued: u64,
+    finished: u64,
+    sent: u64,
+    recent: VecDeque<(Instant, u64)>,
+}
+
+/// Upload rates in bytes per second and what is left of the queue.
+pub struct Throughput {
+    pub current: u64,
+    pub average: u64,
+    pub remaining: u64,
+}
+
+impl Throughput {
+    /// Time left at the average rate so far.
+    pub fn eta(&self) -> Option<Duration> {
+        (self.average > 0 && self.remaining > 0).then(|| Duration::from_secs(self.remaining / self.average))
+    }
+}
+
+impl fmt::Display for Throughput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/s now, {}/s average, {} to go", HumanBytes(self.current), HumanBytes(self.average), HumanBytes(self.remaining))?;
+        match self.eta() {
+            Some(eta) => write!(f, ", about {} left", HumanDuration(eta)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Status {
+    pub fn set_queue(&self, names: Vec<String>) {
+        self.inner.lock().unwrap().queue = names.into();
+    }
+
+    pub fn enqueue(&self, name: &str) {
+        self.inner.lock().unwrap().queue.push_back(name.to_string());
+    }
+
+    pub fn dequeue(&self, name: &str) {
+        self.inner.lock().unwrap().queue.retain(|n| n != name);
+    }
+
+    pub fn start_upload(&self, name: &str, total: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.active.insert(name.to_string(), (0, total));
+        inner.bars.insert(name.to_string(), progress::file(name, total));
+    }
+
+    pub fn advance(&self, name: &str, bytes: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some((sent, _)) = inner.active.get_mut(name) {
+            *sent += bytes;
+        }
+        if let Some(bar) = inner.bars.get(name) {
+            bar.inc(bytes);
+        }
+        let now = Instant::now();
+        let transfer = &mut inner.transfer;
+        transfer.started.get_or_insert(now);
+        transfer.sent += bytes;
+        transfer.recent.push_back((now, bytes));
+        while transfer.recent.front().is_some_and(|(at, _)| now - *at > RATE_WINDOW) {
+            transfer.recent.pop_front();
+        }
+    }
+
+    pub fn finish_upload(&self, name: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.active.remove(name);
+        if let Some(bar) = inner.bars.remove(name) {
+            progress::remove(&bar);
+        }
+    }
+
+    /// Starts counting bytes afresh for a new pass.
+    pub fn reset_transfer(&self) {
+        self.inner.lock().unwrap().transfer = Transfer::default();
+    }
+
+    pub fn queue_bytes(&self, bytes: u64) {
+        self.inner.lock().unwrap().transfer.queued += bytes;
+    }
+
+    pub fn finish_bytes(&self, bytes: u64) {
+        self.inner.lock().unwrap().transfer.finished += bytes;
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.inner.lock().unwrap().transfer.sent
+    }
+
+    /// Rates and bytes left for this pass, once something has been sent.
+    pub fn throughput(&self) -> Option<Throughput> {
+        self.inner.lock().unwrap().throughput()
+    }
+
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+        self.resumed.notify_waiters();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Blocks new uploads from starting while paused; active ones are left to finish.
+    pub async fn wait_while_paused(&self) {
+        while self.is_paused() {
+            let resumed = self.resumed.notified();
+            if !self.is_paused() {
+                break;
+            }
+            resumed.await;
+        }
+    }
+
+    pub fn record_error(&self, message: String) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.errors.len() == MAX_RECENT_ERRORS {
+            inner.errors.pop_front();
+        }
+        inner.errors.push_back(message);
+    }
+
+    /// Adds what a pass did to the running totals and keeps its summary.
+    pub fn count_pass(&self, summary: &Summary) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.totals.uploads += summary.uploaded as u64;
+        inner.totals.failures += summary.failed as u64;
+        inner.totals.bytes += summary.bytes;
+        inner.last_summary = Some(summary.clone());
+    }
+
+    /// The summary of the last pass that got as far as uploading.
+    pub fn last_summary(&self) -> Option<Summary> {
+        self.inner.lock().unwrap().last_summary.clone()
+    }
+
+    pub fn totals(&self) -> Totals {
+        self.inner.lock().unwrap().totals.clone()
+    }
+
+    pub fn queue_depth(&self) -> usize {
+        self.inner.lock().unwrap().queue.len()
+    }
+
+    pub fn active_uploads(&self) -> usize {
+        self.inner.lock().unwrap().active.len()
+    }
+
+    /// Remembers how the last pass ended; `failures` is the consecutive failure count.
+    pub fn record_pass(&self, failures: u32, error: Option<String>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.consecutive_failures = failures;
+        inner.last_failure = error;
+    }
+
+    pub fn consecutive_failures(&self) -> u32 {
+        self.inner.lock().unwrap().consecutive_failures
+    }
+
+    pub fn last_failure(&self) -> Option<String> {
+        self.inner.lock().unwrap().last_failure.clone()
+    }
+
+    /// True if the previous pass failed with exactly this error.
+    pub fn repeats_last_failure(&self, error: &str) -> bool {
+        self.inner.lock().unwrap().last_failure.as_deref() == Some(error)
+    }
+
+    /// Writes the current queue, active uploads and recent errors to the log.
+    pub fn dump(&self) {
+        let inner = self.inner.lock().unwrap();
+        info!("--- Status ---");
+        if self.is_paused() {
+            info!("Uploads are PAUSED");
+        }
+        if inner.consecutive_failures >= DEGRADED_AFTER {
+            warn!("DEGRADED: the last {} passes failed", inner.consecutive_failures);
+        }
+        info!("Queued: {} file(s)", inner.queue.len());
+        for name in &inner.queue {
+            info!("   {}", name);
+        }
+        info!("Active uploads: {}", inner.active.len());
+        for (name, (sent, total)) in &inner.active {
+            let percent = if *total > 0 { sent * 100 / total } else { 100 };
+            info!("   {} - {}% ({}/{} bytes)", name, percent, sent, total);
+        }
+        if let Some(throughput) = inner.throughput() {
+            info!("Throughput: {}", throughput);
+        }
+        info!("Recent errors: {}", inner.errors.len());
+        for message in &inner.errors {
+            info!("   {}", message);
+        }
+    }
+}
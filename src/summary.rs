@@ -0,0 +1,101 @@
+use indicatif::{HumanBytes, HumanDuration};
+use log::info;
+use std::fmt;
+use std::time::Duration;
+
+/// What one sync pass did with the files it found, logged when it ends.
+#[derive(Clone, Default)]
+pub struct Summary {
+    pub run_id: String,
+    pub scanned: usize,
+    /// Already in the upload history, under their own name or an earlier one
+    pub in_history: usize,
+    /// Already on the server: matched by checksum, or rejected as a duplicate
+    pub duplicates: usize,
+    pub uploaded: usize,
+    /// Name and asset id of each uploaded file
+    pub uploads: Vec<(String, String)>,
+    pub failed: usize,
+    /// Name and error of each failed file
+    pub failures: Vec<(String, String)>,
+    /// Left for later or out for good: rules, dead letters, the request budget, the quota
+    pub skipped: usize,
+    /// Each duplicate and skipped file, with why
+    pub skips: Vec<Skip>,
+    /// Bytes sent to the server, Live Photo videos included
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+/// A file the pass didn't upload, and why. The asset id is set for files matched on
+/// the server.
+#[derive(Clone)]
+pub struct Skip {
+    pub file: String,
+    pub reason: String,
+    pub asset_id: Option<String>,
+}
+
+impl Summary {
+    pub fn upload(&mut self, file: &str, asset_id: &str) {
+        self.uploaded += 1;
+        self.uploads.push((file.to_string(), asset_id.to_string()));
+    }
+
+    pub fn duplicate(&mut self, file: &str, reason: &str, asset_id: Option<&str>) {
+        self.duplicates += 1;
+        self.skips.push(Skip { file: file.to_string(), reason: reason.to_string(), asset_id: asset_id.map(str::to_string) });
+    }
+
+    pub fn skip(&mut self, file: &str, reason: &str) {
+        self.skipped += 1;
+        self.skips.push(Skip { file: file.to_string(), reason: reason.to_string(), asset_id: None });
+    }
+
+    pub fn fail(&mut self, file: &str, error: &anyhow::Error) {
+        self.failed += 1;
+        self.failures.push((file.to_string(), format!("{:#}", error)));
+    }
+
+    pub fn log(&self) {
+        if self.uploaded + self.duplicates + self.failed == 0 {
+            info!(
+                action = "summary", run = self.run_id.as_str(), scanned = self.scanned, in_history = self.in_history, skipped = self.skipped;
+                "No new screenshots found among {} file(s) (run {}).", self.scanned, self.run_id
+            );
+            return;
+        }
+        info!(
+            action = "summary", run = self.run_id.as_str(), scanned = self.scanned, in_history = self.in_history,
+            duplicates = self.duplicates, uploaded = self.uploaded, failed = self.failed, skipped = self.skipped,
+            bytes = self.bytes, duration_ms = self.elapsed.as_millis() as u64;
+            "--- Summary (run {}) ---", self.run_id
+        );
+        for line in self.to_string().lines() {
+            info!("{}", line);
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Scanned:           {}", self.scanned)?;
+        writeln!(f, "In history:        {}", self.in_history)?;
+        writeln!(f, "Already on server: {}", self.duplicates)?;
+        writeln!(f, "Uploaded:          {}", self.uploaded)?;
+        writeln!(f, "Failed:            {}", self.failed)?;
+        writeln!(f, "Skipped:           {}", self.skipped)?;
+        let seconds = self.elapsed.as_secs_f64().max(1.0);
+        write!(
+            f,
+            "Transferred {} in {} ({}/s)",
+            HumanBytes(self.bytes),
+            HumanDuration(self.elapsed),
+            HumanBytes((self.bytes as f64 / seconds) as u64)
+        )
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,1009 @@
+use crate::album_cache::AlbumCache;
+use crate::albums;
+use crate::archive;
+use crate::burst::find_bursts;
+use crate::api::{
+    AssetMeta, DUPLICATE_UNKNOWN_ID, Uploader, add_to_album, create_album, create_stack, find_all_by_checksum, find_by_checksum, find_user_id, get_active_url,
+    get_album_id, is_not_found, remove_from_album, restore_from_trash, share_album, tag_assets, upsert_tag,
+    validate_key,
+};
+use crate::config::{Config, DatedAlbums, ModifiedFiles, TrashedPolicy};
+use crate::dead_letter::DeadLetters;
+use crate::error::{SyncError, classify};
+use crate::gpx::Tracks;
+use crate::handler::handler_for;
+use crate::health;
+use crate::healthcheck;
+use crate::history::{Content, History, Record, key_path, relative_path};
+use crate::library;
+use crate::ownership::ensure_ours;
+use crate::post_upload::update_metadata;
+use crate::progress;
+use crate::metadata::{keywords, rating};
+use crate::notify;
+use crate::quota::{Quota, is_quota_error};
+use crate::rate_budget;
+use crate::receipts;
+use crate::report;
+use crate::run;
+use crate::schedule;
+use crate::scan::{live_photo_still_for, spawn_scan};
+use crate::shared_link;
+use crate::skips::SkipLog;
+use crate::state;
+use crate::status::Status;
+use crate::summary::Summary;
+use anyhow::{Result, bail};
+use log::{debug, error, info, warn};
+use reqwest::Client;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::{Instrument, Span, info_span, instrument};
+
+/// The server and album a run talks to.
+pub struct Target {
+    pub base_url: String,
+    /// `None` when the album name is a template (resolved per file) or not used at all
+    pub album_id: Option<String>,
+}
+
+/// What an upload task hands back besides the asset ID.
+struct Job {
+    /// Path and content of each file to record in history on success: the file itself,
+    /// then its Live Photo video if one went up with it
+    uploaded: Vec<(PathBuf, Content)>,
+    /// Albums to add the asset to; `None` is the configured album
+    albums: Vec<Option<String>>,
+    tags: Vec<String>,
+    favorite: bool,
+    /// Why nothing was uploaded, when the server already had the content
+    skipped: Option<String>,
+    /// The earlier version of the file, when the upload replaced its asset
+    replaced: Option<Record>,
+    /// Not attempted: the server ran out of space before the upload's turn came
+    over_quota: bool,
+    /// Not attempted: the hourly request budget ran out before the upload's turn came
+    over_budget: bool,
+    /// Not attempted: uploads stopped being allowed (see `schedule::Gate`) before its turn
+    held_off: Option<String>,
+}
+
+/// Picks the reachable server URL and looks up the configured album on it.
+pub async fn resolve_target(client: &Client, config: &Config) -> Result<Target> {
+    let Some(base_url) = get_active_url(client, &config.local_url, &config.ext_url).await else {
+        bail!("Could not connect to any Immich instance.");
+    };
+    if shared_link::is_share_key(&config.api_key) {
+        let album_id = shared_link::album_id(client, &base_url, &config.api_key).await?;
+        return Ok(Target { base_url, album_id: Some(album_id) });
+    }
+    validate_key(client, &base_url, &config.api_key).await?;
+
+    let album_name = &config.album_name;
+    if albums::is_template(album_name) || config.dated_albums == Some(DatedAlbums::Instead) {
+        return Ok(Target { base_url, album_id: None });
+    }
+    info!("Looking for album: '{}'...", album_name);
+    match find_album(client, config, &base_url, album_name, false).await {
+        Ok(Some(album_id)) => Ok(Target { base_url, album_id: Some(album_id) }),
+        Ok(None) => bail!("Album '{}' not found on server!", album_name),
+        Err(e) => Err(e.context("Error fetching albums")),
+    }
+}
+
+/// Looks up an album by name, creating it if it's missing and `create` or the config
+/// allows that. IDs are cached for `album_id_ttl`.
+async fn find_album(client: &Client, config: &Config, base_url: &str, name: &str, create: bool) -> Result<Option<String>> {
+    let mut cache = AlbumCache::load();
+    if let Some(id) = cache.album_id(name, config.album_id_ttl) {
+        return Ok(Some(id.to_string()));
+    }
+    let id = match get_album_id(client, base_url, &config.api_key, name).await? {
+        Some(id) => id,
+        None if !create && !config.create_album_if_missing => return Ok(None),
+        None => {
+            info!("Creating album '{}'...", name);
+            let id = create_album(client, base_url, &config.api_key, name).await?;
+            if !config.share_albums_with.is_empty()
+                && let Err(e) = share_new_album(client, config, base_url, &id).await
+            {
+                warn!("Failed to share album '{}': {:?}", name, e);
+            }
+            id
+        }
+    };
+    cache.remember_album_id(name, &id);
+    if let Err(e) = cache.save() {
+        warn!("Failed to save album cache: {:?}", e);
+    }
+    Ok(Some(id))
+}
+
+/// Shares a freshly created album with the configured users; emails are looked up.
+async fn share_new_album(client: &Client, config: &Config, base_url: &str, album_id: &str) -> Result<()> {
+    let mut users = Vec::new();
+    for share in &config.share_albums_with {
+        if !share.user.contains('@') {
+            users.push((share.user.clone(), share.role));
+            continue;
+        }
+        match find_user_id(client, base_url, &config.api_key, &share.user).await? {
+            Some(id) => users.push((id, share.role)),
+            None => warn!("No user with email {} on the server, not sharing with them.", share.user),
+        }
+    }
+    if !users.is_empty() {
+        share_album(client, base_url, &config.api_key, album_id, &users).await?;
+        info!("   -- Shared with {} user(s)", users.len());
+    }
+    Ok(())
+}
+
+const BUDGET_DEFERRED: &str = "deferred: the hourly request budget is used up";
+const QUOTA_DEFERRED: &str = "deferred: the server is out of storage space";
+
+/// Runs a single upload pass over the configured folders, or only over `only` when given,
+/// against the configured server and then each mirror in turn.
+pub async fn run_sync(client: &Client, config: &Config, status: &Arc<Status>, only: Option<Vec<PathBuf>>) -> Result<()> {
+    // Scheduled (full) passes only; watch events would make a check look busier than it is
+    let ping = config.healthcheck_url.as_deref().filter(|_| only.is_none());
+    if let Some(url) = ping {
+        healthcheck::start(url).await;
+    }
+    let result = sync_server(client, config, status, only.clone()).await;
+    for mirror in &config.mirrors {
+        if status.is_paused() {
+            status.wait_while_paused().await;
+        }
+        info!("Mirroring to {}...", mirror.url);
+        // Failures are tracked in the mirror's own health file, not the main status
+        let mirror_status = Arc::new(Status::default());
+        let mirror_config = config.for_mirror(mirror);
+        let pass = sync_server(client, &mirror_config, &mirror_status, only.clone());
+        if let Err(e) = state::scoped(Path::new(state::MIRRORS_DIR).join(mirror.namespace()), pass).await.and_then(|r| r) {
+            error!("Mirror {} failed: {:?}", mirror.url, e);
+        }
+    }
+    for account in &config.accounts {
+        let only = match &only {
+            Some(paths) => match paths.iter().filter(|p| p.starts_with(&account.path)).cloned().collect::<Vec<_>>() {
+                mine if mine.is_empty() => continue,
+                mine => Some(mine),
+            },
+            None => None,
+        };
+        if status.is_paused() {
+            status.wait_while_paused().await;
+        }
+        info!("Syncing {} to the account of {}...", account.path.display(), account.name);
+        let account_status = Arc::new(Status::default());
+        let account_config = config.for_account(account);
+        let pass = sync_server(client, &account_config, &account_status, only);
+        if let Err(e) = state::scoped(account.state_dir(), pass).await.and_then(|r| r) {
+            error!("Sync for {} failed: {:?}", account.name, e);
+        }
+    }
+    // A pass with failed uploads counts as failed, as it does in the health file
+    let (summary, failure) = match &result {
+        Ok(()) => (status.last_summary(), status.last_failure()),
+        Err(e) => (None, Some(format!("{:#}", e))),
+    };
+    if let Some(url) = ping {
+        healthcheck::finish(url, summary.as_ref(), failure.as_deref()).await;
+    }
+    // Watch events only when they came to something
+    if only.is_none() || failure.is_some() || summary.as_ref().is_some_and(|s| s.uploaded > 0) {
+        notify::run_finished(config, summary.as_ref(), failure.as_deref()).await;
+    }
+    result
+}
+
+/// One upload pass against the server in `config`.
+/// (watch mode passes the paths reported by filesystem events).
+#[instrument(name = "sync_pass", skip_all, fields(server = config.server_url(), run_id))]
+async fn sync_server(client: &Client, config: &Config, status: &Arc<Status>, only: Option<Vec<PathBuf>>) -> Result<()> {
+    let run_id = run::start();
+    Span::current().record("run_id", run_id.as_str());
+    info!("Starting run {}", run_id);
+    let started = Instant::now();
+    if let Some(per_hour) = config.requests_per_hour {
+        rate_budget::start(&config.api_key, per_hour);
+    }
+
+    if let Some(library) = &config.external_library {
+        let error = library::refresh(client, config, library).await.err().map(|e| format!("{:#}", e));
+        if let Some(message) = &error {
+            error!("{}", message);
+        }
+        record_pass(status, error, 0, None);
+        return Ok(());
+    }
+
+    // 1-2. Network Detection & Album ID
+    let Target { base_url, album_id } = match resolve_target(client, config).await {
+        Ok(target) => target,
+        Err(e) => {
+            let message = format!("{:#}", e);
+            // The daemon reports repeated failures itself; don't log the same line every pass
+            if status.repeats_last_failure(&message) {
+                debug!("{}", message);
+            } else {
+                error!("{}", message);
+            }
+            record_pass(status, Some(message), 0, None);
+            return Ok(());
+        }
+    };
+
+    // 3. Open History
+    let mut history = History::open()?;
+    let mut missing = 0;
+    for folder in config.folders.iter().filter(|f| !f.path.exists()) {
+        error!("Screenshots folder not found: {}", folder.path.display());
+        missing += 1;
+    }
+    if missing == config.folders.len() {
+        record_pass(status, Some("Screenshots folder not found".to_string()), 0, None);
+        return Ok(());
+    }
+
+    // 4. Process Files (as the scanner streams them in)
+    let tracks = config.gpx_dir.as_deref().and_then(|dir| match Tracks::load_dir(dir) {
+        Ok(tracks) => Some(tracks),
+        Err(e) => {
+            warn!("Geotagging disabled for this run: {:?}", e);
+            None
+        }
+    });
+
+    let full_scan = only.is_none();
+    let mut scan = spawn_scan(config, only);
+
+    let uploader = Arc::new(Uploader {
+        client: client.clone(),
+        base_url,
+        key: config.api_key.clone(),
+        fields: config.form_fields.clone(),
+        status: status.clone(),
+        date_patterns: config.filename_date_patterns.clone(),
+        downscale: config.downscale,
+        heic_to_jpeg: config.heic_to_jpeg.clone(),
+        strip_gps: config.strip_gps,
+        upload_timeout: config.connection.upload_timeout,
+        stall_timeout: config.connection.stall_timeout,
+        folders: config.folders.clone(),
+    });
+    
+    // Concurrency control: max 5 parallel uploads
+    let semaphore = Arc::new(Semaphore::new(5));
+    let mut join_set = JoinSet::new();
+    let mut scanned = HashSet::new();
+    let mut dead_letters = DeadLetters::load();
+    let mut dead_skipped = 0;
+    let mut skips = SkipLog::load();
+    let mut deferred = 0;
+    let mut held_off: Option<String> = None;
+    let mut held_off_count = 0;
+    let mut quota = Quota::fetch(&uploader, config.quota_stop_percent).await;
+    let quota_exceeded = Arc::new(AtomicBool::new(false));
+    let mut over_quota = 0;
+    let mut renamed = Vec::new();
+    let mut seen = HashSet::new();
+    let mut unrecorded: usize = 0;
+    let mut on_demand = HashSet::new();
+    status.set_queue(Vec::new());
+    let bars = progress::Pass::start(status);
+    let mut summary = Summary { run_id, ..Summary::default() };
+    // Live Photo videos wait for their still; those whose still didn't go up come round
+    // again once the scan is done, to be uploaded on their own
+    let mut held_videos = Vec::new();
+    let mut paired_stills = HashSet::new();
+
+    loop {
+        let (file_path, held) = match scan.recv().await {
+            Some(path) => (path, false),
+            None => match held_videos.pop() {
+                Some(video) => (video, true),
+                None => break,
+            },
+        };
+        let filename = file_path.file_name().unwrap().to_string_lossy().to_string();
+        if !held {
+            scanned.insert(filename.clone());
+            bars.scanned.inc(1);
+            summary.scanned += 1;
+        }
+
+        let file_key = config.file_key(&file_path);
+        if dead_letters.is_dead(&file_key, config.max_attempts) {
+            dead_skipped += 1;
+            unrecorded += 1;
+            let reason = format!("failed {} times (dead letter)", config.max_attempts);
+            summary.skip(&filename, &reason);
+            skips.record(&file_key, reason);
+            continue;
+        }
+
+        // The history goes by content, so renamed and moved files are recognised
+        let content = match history.content_async(&file_path).await {
+            Ok(c) => c,
+            Err(e) => {
+                error!("Failed to hash {}: {:?}", filename, e);
+                status.record_error(format!("{}: {}", filename, e));
+                record_failure(&mut dead_letters, &file_key, &e, config.max_attempts);
+                unrecorded += 1;
+                summary.fail(&filename, &e);
+                continue;
+            }
+        };
+        seen.insert(content.sha1.clone());
+        let job = config.job_for(&file_path);
+        match history.find(&job, &filename, &content)? {
+            Some(name) if name == filename => {
+                skips.record_again(&file_key, "in the upload history");
+                summary.in_history += 1;
+                continue;
+            }
+            Some(old_name) => {
+                skips.record(&file_key, format!("same content as '{}', uploaded before", old_name));
+                renamed.push((old_name, file_path, content));
+                summary.in_history += 1;
+                continue;
+            }
+            None => {}
+        }
+        // The video half of a Live Photo is uploaded together with its still
+        if let Some(still) = live_photo_still_for(&file_path) {
+            if paired_stills.contains(&still) {
+                continue;
+            }
+            let still_name = still.file_name().unwrap().to_string_lossy();
+            let recorded = match history.content_async(&still).await {
+                Ok(c) => history.find(&job, &still_name, &c)?.is_some(),
+                Err(_) => false,
+            };
+            if !recorded && !held {
+                held_videos.push(file_path);
+                continue;
+            }
+            if !recorded {
+                skips.record(&file_key, format!("Live Photo still '{}' wasn't uploaded; the video went up on its own", still_name));
+            }
+        }
+        // Same place, new content: the file was edited after its upload
+        let mut replaces = None;
+        if let Some(previous) = history.at_path(&job, &relative_path(&file_path, &config.folders), &filename)? {
+            match config.modified_files {
+                ModifiedFiles::Upload => info!("{} changed since its upload, uploading the new version", filename),
+                ModifiedFiles::Replace if previous.asset_id.is_some() => replaces = Some(previous),
+                ModifiedFiles::Replace => warn!("{} changed since its upload, but its asset isn't known; uploading it anew", filename),
+                ModifiedFiles::Skip => {
+                    skips.record(&file_key, "changed since its upload (IMMICH_MODIFIED_FILES=skip)");
+                    summary.skip(&filename, "changed since its upload");
+                    continue;
+                }
+            }
+        }
+        unrecorded += 1;
+
+        let actions = config.rules.evaluate(&file_path);
+        if actions.skip {
+            debug!("Skipping {} (rules)", filename);
+            skips.record(&file_key, "skipped by a rule");
+            summary.skip(&filename, "skipped by a rule");
+            continue;
+        }
+
+        // The window closed (or the link became metered) mid-pass: the rest waits for
+        // the next one, while the uploads under way finish
+        if held_off.is_none() {
+            held_off = schedule::blocked_reason(config).await;
+        }
+        if let Some(reason) = &held_off {
+            held_off_count += 1;
+            summary.skip(&filename, &format!("deferred: {}", reason.to_lowercase()));
+            continue;
+        }
+
+        // Out of budget: leave the rest for the next run/pass
+        if rate_budget::is_exhausted(&config.api_key) {
+            deferred += 1;
+            summary.skip(&filename, BUDGET_DEFERRED);
+            continue;
+        }
+        let queued_as = relative_path(&file_path, &config.folders);
+        status.enqueue(&queued_as);
+
+        // The server said no more; anything else would fail the same way
+        if quota_exceeded.load(Ordering::Relaxed) {
+            status.dequeue(&queued_as);
+            over_quota += 1;
+            summary.skip(&filename, QUOTA_DEFERRED);
+            continue;
+        }
+        if let Some(q) = quota.as_mut() {
+            q.refresh_if_stale(&uploader).await;
+            if !q.try_reserve(content.size) {
+                status.dequeue(&queued_as);
+                over_quota += 1;
+                summary.skip(&filename, QUOTA_DEFERRED);
+                continue;
+            }
+        }
+
+        if status.is_paused() {
+            info!("Paused, waiting for resume...");
+            status.wait_while_paused().await;
+        }
+        // Hashed up front so the video lands in history along with the still. A replaced
+        // asset keeps the video it was paired with.
+        let companion = handler_for(&file_path).companion(&file_path).filter(|_| replaces.is_none());
+        let live_video = match companion {
+            Some(video) => match history.content_async(&video).await {
+                Ok(content) => Some((video, content)),
+                Err(e) => {
+                    warn!("Failed to hash Live Photo video of {}, uploading the still alone: {:?}", filename, e);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let favorite = actions.favorite || curated_favorite(config, &file_path);
+        let visibility = actions.visibility.or(config.visibility);
+        let mut tags = config.tags.clone();
+        tags.extend(actions.tags.into_iter().filter(|t| !config.tags.contains(t)));
+        // Rules are more specific than the folder mappings
+        let rule_album = actions.album.as_deref().or_else(|| config.mappings.album_for(&file_path, &config.folders));
+        let albums = albums_for(config, rule_album, &file_path, &mut on_demand);
+
+        // Queued rather than waited for here, so the scan runs ahead and the size of
+        // the whole queue is known for the ETA
+        let bytes = content.size + live_video.as_ref().map_or(0, |(_, c)| c.size);
+        status.queue_bytes(bytes);
+        bars.batch.inc_length(1);
+        let uploader = uploader.clone();
+        let semaphore = semaphore.clone();
+        let trashed_policy = config.trashed_duplicates;
+        let quota_exceeded = quota_exceeded.clone();
+        let gate = schedule::Gate::of(config);
+        let span = info_span!("file", file = filename.as_str(), bytes);
+
+        if live_video.is_some() {
+            paired_stills.insert(file_path.clone());
+        }
+        join_set.spawn(progress::counted(bars.batch.clone(), status.clone(), bytes, async move {
+            uploader.status.wait_while_paused().await;
+            let permit = semaphore.acquire_owned().instrument(info_span!("wait_for_slot")).await.unwrap();
+            uploader.status.dequeue(&queued_as);
+            let mut meta = AssetMeta {
+                favorite,
+                visibility,
+                replaces: replaces.as_ref().and_then(|r| r.asset_id.clone()),
+                ..AssetMeta::default()
+            };
+            let mut job = Job {
+                uploaded: vec![(file_path.clone(), content.clone())],
+                albums,
+                tags,
+                favorite,
+                skipped: None,
+                replaced: replaces,
+                over_quota: false,
+                over_budget: false,
+                held_off: None,
+            };
+            // The server said no more while this one was waiting
+            if quota_exceeded.load(Ordering::Relaxed) {
+                job.over_quota = true;
+                return (job, Ok(DUPLICATE_UNKNOWN_ID.to_string()));
+            }
+            // Checked again now that the uploads ahead of this one have spent theirs
+            if rate_budget::is_exhausted(&uploader.key) {
+                job.over_budget = true;
+                return (job, Ok(DUPLICATE_UNKNOWN_ID.to_string()));
+            }
+            // A long backlog can outlast the upload window
+            if let Some(reason) = gate.blocked_reason().await {
+                job.held_off = Some(reason);
+                return (job, Ok(DUPLICATE_UNKNOWN_ID.to_string()));
+            }
+            // Content already on the server (e.g. from the phone app): just link it. Live
+            // Photos still go through upload so the video gets paired.
+            if live_video.is_none() {
+                match find_by_checksum(&uploader.client, &uploader.base_url, &uploader.key, &filename, &content.sha1).await {
+                    Ok(Some(existing)) if !existing.is_trashed => {
+                        info!(file = filename.as_str(), action = "link", status = "duplicate"; "Already on server, linking existing asset: {}", filename);
+                        job.skipped = Some(format!("checksum matches server asset {}", existing.asset_id));
+                        drop(permit);
+                        return (job, Ok(existing.asset_id));
+                    }
+                    Ok(Some(existing)) => match trashed_policy {
+                        TrashedPolicy::Restore => {
+                            info!("Restoring {} from the server's trash", filename);
+                            job.skipped = Some(format!("checksum matches trashed asset {} (restored)", existing.asset_id));
+                            let ids = std::slice::from_ref(&existing.asset_id);
+                            let result = restore_from_trash(&uploader.client, &uploader.base_url, &uploader.key, ids).await;
+                            drop(permit);
+                            return (job, result.map(|()| existing.asset_id));
+                        }
+                        TrashedPolicy::Skip => {
+                            info!("{} is in the server's trash, not uploading it again", filename);
+                            job.skipped = Some(format!("checksum matches trashed asset {}", existing.asset_id));
+                            drop(permit);
+                            return (job, Ok(DUPLICATE_UNKNOWN_ID.to_string()));
+                        }
+                        TrashedPolicy::Upload => {}
+                    },
+                    Ok(None) => {}
+                    Err(e) => debug!("Checksum lookup failed for {}, uploading: {:?}", filename, e),
+                }
+            }
+            if let Some((video_path, video_content)) = live_video {
+                let video_name = video_path.file_name().unwrap().to_string_lossy().to_string();
+                info!("Uploading Live Photo video: {}...", video_name);
+                match uploader.upload_asset(&video_path, &AssetMeta::default()).await {
+                    Ok(id) if id == DUPLICATE_UNKNOWN_ID => {
+                        warn!("Server did not return an ID for {}, uploading {} unpaired", video_name, filename);
+                    }
+                    Ok(id) => {
+                        meta.live_photo_video_id = Some(id);
+                        job.uploaded.push((video_path, video_content));
+                    }
+                    Err(e) => {
+                        drop(permit);
+                        return (job, Err(e.context(format!("Live Photo video {}", video_name))));
+                    }
+                }
+            }
+            match &meta.replaces {
+                Some(asset_id) => info!("Replacing the file of asset {} with {}...", asset_id, filename),
+                None => info!("Uploading: {}...", filename),
+            }
+            let result = uploader.upload_asset(&file_path, &meta).await;
+            if result.as_ref().is_err_and(is_quota_error) {
+                quota_exceeded.store(true, Ordering::Relaxed);
+            }
+            drop(permit);
+            (job, result)
+        }.instrument(span)));
+    }
+
+    // Only now do we know which old names are really gone (rather than copied)
+    let moved: Vec<Record> = renamed
+        .into_iter()
+        .filter(|(old_name, _, _)| !scanned.contains(old_name))
+        .map(|(old_name, path, content)| {
+            info!("'{}' was renamed to '{}', updating the history", old_name, file_name(&path));
+            Record::new(config, &path, content, None)
+        })
+        .collect();
+    if let Err(e) = history.insert(&moved) {
+        error!("Failed to update history: {:?}", e);
+    }
+    // Only a complete scan of every folder says a file is really gone
+    if config.unlink_removed
+        && full_scan
+        && missing == 0
+        && config.date_range.is_unbounded()
+        && let Some(album_id) = &album_id
+        && let Err(e) = unlink_removed(&uploader, album_id, &mut history, &config.jobs(), &scanned, &seen).await
+    {
+        error!("Failed to unlink deleted files: {:?}", e);
+    }
+
+    let mut by_album: HashMap<Option<String>, Vec<String>> = HashMap::new();
+    let mut by_tag: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut asset_ids: HashMap<PathBuf, String> = HashMap::new();
+    let mut receipts = Vec::new();
+    let mut uploaded_count = 0;
+    let mut last_failure = None;
+
+    while let Some(res) = join_set.join_next().await {
+        match res {
+            Ok((job, _)) if job.over_quota => {
+                over_quota += 1;
+                summary.skip(&file_name(&job.uploaded[0].0), QUOTA_DEFERRED);
+            }
+            Ok((job, _)) if job.over_budget => {
+                deferred += 1;
+                summary.skip(&file_name(&job.uploaded[0].0), BUDGET_DEFERRED);
+            }
+            Ok((job, _)) if job.held_off.is_some() => {
+                held_off_count += 1;
+                let reason = job.held_off.unwrap_or_default().to_lowercase();
+                summary.skip(&file_name(&job.uploaded[0].0), &format!("deferred: {}", reason));
+            }
+            Ok((job, Ok(asset_id))) => {
+                let filename = &file_name(&job.uploaded[0].0);
+                let unknown_id = asset_id == DUPLICATE_UNKNOWN_ID;
+                match &job.skipped {
+                    Some(reason) => {
+                        skips.record(&config.file_key(&job.uploaded[0].0), reason.as_str());
+                        summary.duplicate(filename, reason, (!unknown_id).then_some(asset_id.as_str()));
+                    }
+                    None if unknown_id => {
+                        skips.record(&config.file_key(&job.uploaded[0].0), "server rejected it as a duplicate");
+                        summary.duplicate(filename, "server rejected it as a duplicate", None);
+                    }
+                    None => summary.upload(filename, &asset_id),
+                }
+                if let Some(target) = &config.archive {
+                    for (path, content) in &job.uploaded {
+                        if let Err(e) = archive::store(target, path, &content.sha1, &asset_id, &config.filename_date_patterns).await {
+                            error!("Failed to archive {}: {:?}", path.display(), e);
+                            status.record_error(format!("Archive {}: {}", path.display(), e));
+                        }
+                    }
+                }
+                if asset_id != DUPLICATE_UNKNOWN_ID {
+                    let favorite = job.favorite && job.skipped.is_some();
+                    update_metadata(&uploader, config, tracks.as_ref(), &job.uploaded[0].0, &asset_id, favorite).await;
+                }
+                if asset_id != DUPLICATE_UNKNOWN_ID {
+                    receipts.push((filename.clone(), asset_id.clone()));
+                    for tag in job.tags {
+                        by_tag.entry(tag).or_default().push(asset_id.clone());
+                    }
+                    asset_ids.insert(job.uploaded[0].0.clone(), asset_id.clone());
+                    for album in job.albums {
+                        by_album.entry(album).or_default().push(asset_id.clone());
+                    }
+                }
+                dead_letters.clear(&config.file_key(&job.uploaded[0].0));
+                if let Some(previous) = &job.replaced
+                    && let Err(e) = history.forget(std::slice::from_ref(previous))
+                {
+                    error!("Failed to save history: {:?}", e);
+                }
+                let known_id = (asset_id != DUPLICATE_UNKNOWN_ID).then_some(asset_id);
+                let uploaded: Vec<Record> = job
+                    .uploaded
+                    .into_iter()
+                    .map(|(path, content)| Record::new(config, &path, content, known_id.clone()))
+                    .collect();
+                if let Err(e) = history.insert(&uploaded) {
+                    error!("Failed to save history: {:?}", e);
+                }
+                uploaded_count += 1;
+            }
+            Ok((job, Err(e))) => {
+                let filename = &file_name(&job.uploaded[0].0);
+                // With a Live Photo it may be the video that failed
+                let failed = classify(&e).and_then(SyncError::path).map(file_name).unwrap_or_else(|| filename.clone());
+                error!(file = failed.as_str(), action = "upload", status = "failed", error:% = e; "Upload error for {}: {:?}", failed, e);
+                status.record_error(format!("{}: {}", filename, e));
+                record_failure(&mut dead_letters, &config.file_key(&job.uploaded[0].0), &e, config.max_attempts);
+                last_failure = Some(format!("{}: {}", filename, e));
+                summary.fail(&failed, &e);
+                notify::upload_failed(config, &summary.run_id, &failed, &e);
+            }
+            Err(e) => error!("Task join error: {:?}", e),
+        }
+    }
+    drop(bars);
+
+    if let Some(target) = &config.archive
+        && let Err(e) = archive::sync_manifest(target).await
+    {
+        error!("Failed to upload archive manifest: {:?}", e);
+    }
+    if let Err(e) = dead_letters.save() {
+        error!("Failed to save dead-letter list: {:?}", e);
+    }
+    if let Err(e) = skips.save() {
+        error!("Failed to save skip log: {:?}", e);
+    }
+    match rate_budget::save(&config.api_key) {
+        Ok(Some(minutes)) if deferred > 0 => {
+            warn!("Hourly request budget used up; deferred {} file(s), next slot in ~{} min.", deferred, minutes);
+        }
+        Ok(_) => {}
+        Err(e) => error!("Failed to save rate budget: {:?}", e),
+    }
+    if held_off_count > 0 {
+        info!("Uploads aren't allowed anymore; left {} file(s) for later.", held_off_count);
+    }
+    if over_quota > 0 {
+        warn!("Out of storage space on the server; deferred {} file(s) to a later run.", over_quota);
+    }
+    if dead_skipped > 0 {
+        warn!(
+            "Skipped {} file(s) that failed {} times; see `dead-letter list`.",
+            dead_skipped, config.max_attempts
+        );
+    }
+
+    for (album, asset_ids) in by_album {
+        // Files without a rule album go to the configured one
+        let target_id = match &album {
+            None => match &album_id {
+                Some(id) => id.clone(),
+                None => continue,
+            },
+            Some(name) => match find_album(client, config, &uploader.base_url, name, on_demand.contains(name)).await {
+                Ok(Some(id)) => id,
+                Ok(None) => {
+                    warn!("Rule album '{}' not found on server; {} asset(s) not linked.", name, asset_ids.len());
+                    continue;
+                }
+                Err(e) => {
+                    error!("Error looking up album '{}': {:?}", name, e);
+                    continue;
+                }
+            },
+        };
+        match link_to_album(client, &uploader.base_url, &uploader.key, &target_id, asset_ids.clone()).await {
+            Ok(()) => record_album(&mut history, &asset_ids, &target_id),
+            Err(e) => {
+                // A cached ID of an album that was since deleted (or recreated): look it up once more
+                let name = album.as_deref().unwrap_or(&config.album_name);
+                warn!("{:#}; looking up album '{}' again", e, name);
+                match find_album(client, config, &uploader.base_url, name, album.as_ref().is_some_and(|n| on_demand.contains(n))).await {
+                    Ok(Some(id)) => match link_to_album(client, &uploader.base_url, &uploader.key, &id, asset_ids.clone()).await {
+                        Ok(()) => record_album(&mut history, &asset_ids, &id),
+                        Err(e) => error!("Failed to link to album '{}': {:#}", name, e),
+                    },
+                    Ok(None) => warn!("Album '{}' not found on server; {} asset(s) not linked.", name, asset_ids.len()),
+                    Err(e) => error!("Error looking up album '{}': {:?}", name, e),
+                }
+            }
+        }
+    }
+    for (tag, ids) in by_tag {
+        if let Err(e) = apply_tag(client, &uploader.base_url, &uploader.key, &tag, &ids).await {
+            error!("Failed to tag {} asset(s) with '{}': {:?}", ids.len(), tag, e);
+        }
+    }
+    if config.stack_raw_jpeg {
+        stack_raw_pairs(&uploader, &asset_ids).await;
+    }
+    if let Some(window) = config.burst_window {
+        stack_bursts(&uploader, config, &asset_ids, window).await;
+    }
+
+    if let Some(grace) = config.receipt_grace
+        && let Err(e) = receipts::verify(client, &uploader.base_url, &uploader.key, receipts, grace).await
+    {
+        warn!("Failed to verify uploads: {:?}", e);
+    }
+
+    let backlog = full_scan.then(|| unrecorded.saturating_sub(uploaded_count));
+    record_pass(status, last_failure, uploaded_count, backlog);
+
+    summary.bytes = status.bytes_sent();
+    summary.elapsed = started.elapsed();
+    summary.log();
+    // Passes that found nothing new leave no report behind
+    let eventful = !summary.uploads.is_empty() || !summary.skips.is_empty() || !summary.failures.is_empty();
+    if let Some(dir) = config.report_dir.as_ref().filter(|_| eventful) {
+        match report::write(dir, config.report_format, config.server_url(), &config.album_name, &summary) {
+            Ok(path) => info!("Report written to {}", path.display()),
+            Err(e) => warn!("Failed to write the report: {:#}", e),
+        }
+    }
+    status.count_pass(&summary);
+
+    Ok(())
+}
+
+/// Records how a pass ended in the health file and the live status.
+pub fn record_pass(status: &Status, error: Option<String>, uploaded: usize, backlog: Option<usize>) {
+    let failures = health::record_run(error.clone(), uploaded, backlog);
+    status.record_pass(failures, error);
+}
+
+/// The albums a file goes into: the main one plus, if enabled, its dated albums.
+fn albums_for(config: &Config, rule_album: Option<&str>, path: &Path, on_demand: &mut HashSet<String>) -> Vec<Option<String>> {
+    // A shared link only reaches its own album
+    if shared_link::is_share_key(&config.api_key) {
+        return vec![None];
+    }
+    let mut albums = Vec::new();
+    let main = album_for(config, rule_album, path, on_demand);
+    if main.is_some() || config.dated_albums != Some(DatedAlbums::Instead) {
+        albums.push(main);
+    }
+    if config.dated_albums.is_some() {
+        for template in ["{YYYY}", "{YYYY}-{MM}"] {
+            let name = albums::render(template, path, &config.filename_date_patterns);
+            on_demand.insert(name.clone());
+            albums.push(Some(name));
+        }
+    }
+    albums
+}
+
+/// The album a file goes into: its rule (or mapped) album, else its subfolder's album (if enabled),
+/// else the configured one. `None` stands for the configured album, unless that's a
+/// template. Names of albums to create when missing are added to `on_demand`.
+fn album_for(config: &Config, rule_album: Option<&str>, path: &Path, on_demand: &mut HashSet<String>) -> Option<String> {
+    if rule_album.is_none()
+        && config.album_per_subfolder
+        && let Some(name) = albums::subfolder(path, &config.folders)
+    {
+        on_demand.insert(name.clone());
+        return Some(name);
+    }
+    let template = rule_album.or_else(|| albums::is_template(&config.album_name).then_some(config.album_name.as_str()))?;
+    let name = albums::render(template, path, &config.filename_date_patterns);
+    // Dated albums are created as the months come
+    if albums::is_template(template) {
+        on_demand.insert(name.clone());
+    }
+    Some(name)
+}
+
+/// Takes the assets of history entries no longer found locally (by name or content) out of
+/// the albums they were added to, and forgets them. Only assets this tool uploaded are
+/// touched.
+async fn unlink_removed(
+    uploader: &Uploader,
+    album_id: &str,
+    history: &mut History,
+    jobs: &[String],
+    scanned: &HashSet<String>,
+    seen: &HashSet<String>,
+) -> Result<()> {
+    let (client, base_url, key) = (&uploader.client, &uploader.base_url, &uploader.key);
+    let mut unlink: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut forget = Vec::new();
+    for job in jobs {
+        let mut gone = history.hashed(job)?;
+        gone.retain(|(name, hash)| !scanned.contains(name) && !seen.contains(hash));
+        for batch in gone.chunks(500) {
+            let files: Vec<(&str, &str)> = batch.iter().map(|(name, hash)| (name.as_str(), hash.as_str())).collect();
+            let mut found = find_all_by_checksum(client, base_url, key, &files).await?;
+            for (name, hash) in batch {
+                if let Some(m) = found.remove(name).filter(|m| !m.is_trashed) {
+                    match ensure_ours(client, base_url, key, &m.asset_id).await {
+                        Ok(()) => {
+                            info!("   -- {} was deleted locally, removing it from its albums", name);
+                            // Entries from before albums were noted only know the main one
+                            let mut albums = history.albums(job, hash)?;
+                            if albums.is_empty() {
+                                albums.push(album_id.to_string());
+                            }
+                            for album in albums {
+                                unlink.entry(album).or_default().push(m.asset_id.clone());
+                            }
+                        }
+                        Err(e) => warn!("Leaving {} in the album: {:#}", name, e),
+                    }
+                }
+            }
+        }
+        forget.push((job, gone.into_iter().map(|(_, hash)| hash).collect::<Vec<_>>()));
+    }
+    for (album, assets) in &unlink {
+        for chunk in assets.chunks(50) {
+            remove_from_album(client, base_url, key, album, chunk).await?;
+        }
+        info!("Removed {} deleted file(s) from album {}.", assets.len(), album);
+    }
+    for (job, hashes) in forget {
+        history.remove(job, &hashes)?;
+    }
+    Ok(())
+}
+
+/// Assets added to an album per request. A failed batch is retried in halves, so one bad
+/// ID (or a server with a lower request size limit) doesn't leave the rest unlinked.
+const ALBUM_BATCH: usize = 500;
+
+/// Adds assets to the album in batches, skipping any the album cache says are already there.
+/// Fails only if the album doesn't exist (anymore); other errors are logged.
+pub async fn link_to_album(client: &Client, base_url: &str, key: &str, album_id: &str, mut asset_ids: Vec<String>) -> Result<()> {
+    let mut cache = AlbumCache::load();
+    match cache.members(client, base_url, key, album_id).await {
+        Ok(existing) => asset_ids.retain(|id| !existing.contains(id)),
+        Err(e) if is_not_found(&e) => {
+            cache.forget_album(album_id);
+            cache.save()?;
+            bail!("Album {} no longer exists", album_id);
+        }
+        Err(e) => warn!("Could not read album membership, linking everything: {:?}", e),
+    }
+    if asset_ids.is_empty() {
+        info!("All assets are already in the album.");
+        return Ok(());
+    }
+
+    info!("Adding {} assets to album in batches...", asset_ids.len());
+    let mut batches: Vec<&[String]> = asset_ids.chunks(ALBUM_BATCH).rev().collect();
+    while let Some(batch) = batches.pop() {
+        match add_to_album(client, base_url, key, album_id, batch).await {
+            Ok(()) => {
+                if let Err(e) = cache.record_added(client, base_url, key, album_id, batch).await {
+                    warn!("Failed to update album cache: {:?}", e);
+                }
+            }
+            Err(e) if batch.len() > 1 => {
+                warn!("Linking {} assets failed, retrying in smaller batches: {:#}", batch.len(), e);
+                let (first, second) = batch.split_at(batch.len() / 2);
+                batches.push(second);
+                batches.push(first);
+            }
+            Err(e) => error!("Failed to link asset {} to album: {:?}", batch[0], e),
+        }
+    }
+    if let Err(e) = cache.save() {
+        warn!("Failed to save album cache: {:?}", e);
+    }
+    Ok(())
+}
+
+/// Whether the rating or keywords given in an editor such as Lightroom make this a favorite.
+fn curated_favorite(config: &Config, path: &Path) -> bool {
+    config.favorite_min_rating.is_some_and(|min| rating(path).is_some_and(|r| r >= min))
+        || config
+            .favorite_keyword
+            .as_ref()
+            .is_some_and(|wanted| keywords(path).iter().any(|k| k.eq_ignore_ascii_case(wanted)))
+}
+
+/// Stacks each RAW uploaded this run with its JPEG, if that went up this run too
+/// (earlier uploads have no asset ID on record).
+async fn stack_raw_pairs(uploader: &Uploader, asset_ids: &HashMap<PathBuf, String>) {
+    for (path, raw_id) in asset_ids {
+        let Some(jpeg_id) = handler_for(path).stack_under(path).and_then(|jpeg| asset_ids.get(&jpeg)) else {
+            continue;
+        };
+        let ids = [jpeg_id.clone(), raw_id.clone()];
+        match create_stack(&uploader.client, &uploader.base_url, &uploader.key, &ids).await {
+            Ok(()) => info!("   -- Stacked {} with its JPEG", path.display()),
+            Err(e) => warn!("Failed to stack {}: {:?}", path.display(), e),
+        }
+    }
+}
+
+/// Stacks burst sequences uploaded this run, first frame on top. RAW files already
+/// stacked with their JPEG are left out (an asset can only be in one stack).
+async fn stack_bursts(uploader: &Uploader, config: &Config, asset_ids: &HashMap<PathBuf, String>, window: std::time::Duration) {
+    let files: Vec<&Path> = asset_ids
+        .keys()
+        .filter(|p| !(config.stack_raw_jpeg && handler_for(p).stack_under(p).is_some_and(|j| asset_ids.contains_key(&j))))
+        .map(|p| p.as_path())
+        .collect();
+    let window = chrono::Duration::from_std(window).unwrap_or(chrono::Duration::MAX);
+    for burst in find_bursts(&files, window, config.burst_min_frames, &config.filename_date_patterns) {
+        let ids: Vec<String> = burst.iter().map(|p| asset_ids[p].clone()).collect();
+        match create_stack(&uploader.client, &uploader.base_url, &uploader.key, &ids).await {
+            Ok(()) => info!("   -- Stacked burst of {} starting at {}", ids.len(), burst[0].display()),
+            Err(e) => warn!("Failed to stack burst at {}: {:?}", burst[0].display(), e),
+        }
+    }
+}
+
+async fn apply_tag(client: &Client, base_url: &str, key: &str, tag: &str, asset_ids: &[String]) -> Result<()> {
+    let tag_id = upsert_tag(client, base_url, key, tag).await?;
+    tag_assets(client, base_url, key, &tag_id, asset_ids).await?;
+    info!("   -- Tagged {} assets with '{}'", asset_ids.len(), tag);
+    Ok(())
+}
+
+fn record_album(history: &mut History, asset_ids: &[String], album_id: &str) {
+    if let Err(e) = history.set_album(asset_ids, album_id) {
+        error!("Failed to save history: {:?}", e);
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap().to_string_lossy().to_string()
+}
+
+fn record_failure(dead_letters: &mut DeadLetters, key: &str, error: &anyhow::Error, max_attempts: u32) {
+    // An outage or a full quota would otherwise dead-letter every file that was due
+    if classify(error).is_some_and(SyncError::is_environmental) {
+        return;
+    }
+    let attempts = dead_letters.record_failure(key, error.to_string());
+    if max_attempts > 0 && attempts == max_attempts {
+        warn!("Giving up on {} after {} failed attempts.", key_path(key).display(), attempts);
+    }
+}
@@ -0,0 +1,49 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use tracing::Level;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::{Layer, SubscriberExt};
+
+const SERVICE_NAME: &str = "immich_sync";
+
+/// Sends the pipeline's spans (sync pass, scan, hash, checksum lookup, upload, album)
+/// to an OTLP/HTTP collector such as Jaeger or Tempo. Spans still queued are flushed
+/// when this is dropped.
+pub struct Telemetry(SdkTracerProvider);
+
+/// Starts exporting to `endpoint`, the collector's base URL (`http://localhost:4318`)
+/// or its full `/v1/traces` URL.
+pub fn init(endpoint: &str) -> Result<Telemetry> {
+    let endpoint = endpoint.trim_end_matches('/');
+    let url = if endpoint.ends_with("/v1/traces") { endpoint.to_string() } else { format!("{}/v1/traces", endpoint) };
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(&url)
+        .build()
+        .context("Failed to set up the OTLP exporter")?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+        .build();
+
+    // Our own spans only: the HTTP and TLS crates' are too fine-grained to be of use
+    let layer = tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer(SERVICE_NAME))
+        .with_filter(Targets::new().with_target(SERVICE_NAME, Level::INFO));
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))
+        .context("Failed to install the tracing subscriber")?;
+    info!("Exporting traces to {}", url);
+    Ok(Telemetry(provider))
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Err(e) = self.0.shutdown() {
+            warn!("Failed to flush traces: {}", e);
+        }
+    }
+}
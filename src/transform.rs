@@ -0,0 +1,356 @@
+use anyhow::{Context, Result, bail};
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, imageops::FilterType};
+use std::io::Cursor;
+use std::path::Path;
+use tokio::process::Command;
+
+const DOWNSCALE_FORMATS: &[ImageFormat] = &[ImageFormat::Jpeg, ImageFormat::Png, ImageFormat::WebP];
+
+/// Opt-in shrinking of big images before upload, for servers short on storage.
+#[derive(Clone, Copy)]
+pub struct Downscale {
+    /// Longest side in pixels, e.g. 3840 for 4K
+    pub max_dimension: Option<u32>,
+    /// Files above this size are re-encoded even if small enough in pixels
+    pub max_bytes: Option<u64>,
+    pub quality: u8,
+}
+
+impl Downscale {
+    /// Returns a JPEG rendition when `data` is over the limits, `None` to upload it as is.
+    /// `data` is what would otherwise go up, so a HEIC already converted to JPEG counts.
+    /// Metadata isn't carried over, so the pixels are turned upright per the EXIF
+    /// orientation first; the capture date goes with the upload form instead.
+    pub fn apply(&self, data: &[u8]) -> Result<Option<Vec<u8>>> {
+        let reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+        if !reader.format().is_some_and(|f| DOWNSCALE_FORMATS.contains(&f)) {
+            return Ok(None);
+        }
+        let size = data.len() as u64;
+        let (width, height) = reader.into_dimensions()?;
+        let too_large = self.max_dimension.is_some_and(|max| width.max(height) > max);
+        let too_heavy = self.max_bytes.is_some_and(|max| size > max);
+        if !too_large && !too_heavy {
+            return Ok(None);
+        }
+
+        let mut decoder = ImageReader::new(Cursor::new(data)).with_guessed_format()?.into_decoder()?;
+        let orientation = decoder.orientation()?;
+        let mut img = DynamicImage::from_decoder(decoder)?;
+        img.apply_orientation(orientation);
+        if let Some(max) = self.max_dimension
+            && too_large
+        {
+            img = img.resize(max, max, FilterType::Lanczos3);
+        }
+        let mut out = Vec::new();
+        JpegEncoder::new_with_quality(&mut out, self.quality).encode_image(&img.to_rgb8())?;
+        // Recompressing can backfire (e.g. small flat PNGs); keep whichever is smaller
+        Ok((too_large || (out.len() as u64) < size).then_some(out))
+    }
+}
+
+/// Converters tried in order when none is configured; all of them keep the EXIF block.
+const HEIC_CONVERTERS: &[&str] = &[
+    "heif-convert -q {quality} {input} {output}",
+    "magick {input} -quality {quality} {output}",
+    "sips -s format jpeg -s formatOptions {quality} {input} --out {output}",
+];
+
+/// Local HEIC/HEIF -> JPEG conversion for servers that are slow to transcode HEIC.
+#[derive(Clone)]
+pub struct HeicToJpeg {
+    /// Command template with `{input}`, `{output}` and `{quality}` placeholders
+    pub converter: Option<String>,
+    pub quality: u8,
+}
+
+impl HeicToJpeg {
+    pub fn applies_to(path: &Path) -> bool {
+        path.extension().is_some_and(|e| e.eq_ignore_ascii_case("heic") || e.eq_ignore_ascii_case("heif"))
+    }
+
+    /// Runs the converter and returns the JPEG bytes.
+    pub async fn convert(&self, path: &Path) -> Result<Vec<u8>> {
+        // Created exclusively under a random name, so no other user can guess it or plant
+        // a link there; removed when dropped, whichever way this returns
+        let output = tempfile::Builder::new().prefix("immich-sync-").suffix(".jpg").tempfile()?;
+        let templates: Vec<&str> = match &self.converter {
+            Some(template) => vec![template.as_str()],
+            None => HEIC_CONVERTERS.to_vec(),
+        };
+        let mut last_error = None;
+        for template in templates {
+            let mut words = template.split_whitespace().map(|word| match word {
+                "{input}" => path.as_os_str().to_owned(),
+                "{output}" => output.path().as_os_str().to_owned(),
+                other => other.replace("{quality}", &self.quality.to_string()).into(),
+            });
+            let Some(program) = words.next() else {
+                continue;
+            };
+            match Command::new(&program).args(words).output().await {
+                Ok(result) if result.status.success() => {
+                    let jpeg = tokio::fs::read(output.path()).await.context("Failed to read the converted file")?;
+                    if jpeg.is_empty() {
+                        bail!("Converter produced no output");
+                    }
+                    return Ok(jpeg);
+                }
+                Ok(result) => {
+                    last_error = Some(format!("{}: {}", program.to_string_lossy(), String::from_utf8_lossy(&result.stderr).trim()))
+                }
+                Err(e) => last_error = Some(format!("{}: {}", program.to_string_lossy(), e)),
+            }
+        }
+        bail!("HEIC conversion failed ({})", last_error.unwrap_or_else(|| "no converter".to_string()))
+    }
+}
+
+/// Removes the location from a JPEG's EXIF and XMP data in place and returns whether
+/// there was one. The GPS directory is emptied and its values zeroed, and the XMP GPS
+/// values blanked, rather than cut out, so every other offset in the file stays valid.
+/// Other formats are left untouched.
+pub fn strip_gps(jpeg: &mut [u8]) -> bool {
+    if !jpeg.starts_with(&[0xFF, 0xD8]) {
+        return false;
+    }
+    let mut stripped = false;
+    let mut i = 2;
+    while i + 4 <= jpeg.len() && jpeg[i] == 0xFF {
+        let marker = jpeg[i + 1];
+        // Fill bytes and markers without a length
+        if marker == 0xFF || (0xD0..=0xD7).contains(&marker) || marker == 0x01 {
+            i += if marker == 0xFF { 1 } else { 2 };
+            continue;
+        }
+        // Image data follows; metadata segments all come before it
+        if marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+        let length = u16::from_be_bytes([jpeg[i + 2], jpeg[i + 3]]) as usize;
+        let end = (i + 2 + length).min(jpeg.len());
+        if marker == 0xE1 && jpeg.get(i + 4..end).is_some_and(|s| s.starts_with(b"Exif\0\0")) {
+            stripped |= clear_gps_ifd(&mut jpeg[i + 10..end]).is_some();
+        } else if marker == 0xE1 && jpeg.get(i + 4..end).is_some_and(|s| s.starts_with(XMP_HEADER)) {
+            stripped |= blank_xmp_gps(&mut jpeg[i + 4 + XMP_HEADER.len()..end]);
+        }
+        i = end.max(i + 4);
+    }
+    stripped
+}
+
+const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// Overwrites the values of the `exif:GPS*` properties in an XMP packet with spaces,
+/// whether written as attributes or as elements. Returns whether there were any.
+fn blank_xmp_gps(xmp: &mut [u8]) -> bool {
+    const PREFIX: &[u8] = b"exif:GPS";
+    let mut blanked = false;
+    let mut i = 0;
+    while let Some(at) = xmp[i..].windows(PREFIX.len()).position(|w| w == PREFIX) {
+        let start = i + at;
+        let name_end = start + xmp[start..].iter().position(|b| !b.is_ascii_alphanumeric() && *b != b':').unwrap_or(xmp.len() - start);
+        i = name_end;
+        // `exif:GPSLatitude="..."` or `<exif:GPSLatitude>...</exif:GPSLatitude>`
+        let value = match xmp.get(name_end..name_end + 2) {
+            Some([b'=', quote @ (b'"' | b'\'')]) => {
+                let quote = *quote;
+                let from = name_end + 2;
+                xmp[from..].iter().position(|&b| b == quote).map(|len| from..from + len)
+            }
+            Some([b'>', _]) if start > 0 && xmp[start - 1] == b'<' => {
+                let from = name_end + 1;
+                xmp[from..].iter().position(|&b| b == b'<').map(|len| from..from + len)
+            }
+            _ => None,
+        };
+        if let Some(value) = value {
+            blanked |= xmp[value.clone()].iter().any(|b| !b.is_ascii_whitespace());
+            i = value.end;
+            xmp[value].fill(b' ');
+        }
+    }
+    blanked
+}
+
+/// Empties the GPS IFD of a TIFF-structured EXIF block; `None` if there is none.
+fn clear_gps_ifd(tiff: &mut [u8]) -> Option<()> {
+    let little = match tiff.get(..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let read16 = |t: &[u8], at: usize| -> Option<usize> {
+        let b: [u8; 2] = t.get(at..at + 2)?.try_into().ok()?;
+        Some(if little { u16::from_le_bytes(b) } else { u16::from_be_bytes(b) } as usize)
+    };
+    let read32 = |t: &[u8], at: usize| -> Option<usize> {
+        let b: [u8; 4] = t.get(at..at + 4)?.try_into().ok()?;
+        Some(if little { u32::from_le_bytes(b) } else { u32::from_be_bytes(b) } as usize)
+    };
+
+    let ifd0 = read32(tiff, 4)?;
+    let gps = (0..read16(tiff, ifd0)?)
+        .map(|n| ifd0 + 2 + n * 12)
+        .find(|&entry| read16(tiff, entry) == Some(0x8825))
+        .and_then(|entry| read32(tiff, entry + 8))?;
+    let count = read16(tiff, gps)?;
+    for n in 0..count {
+        let entry = gps + 2 + n * 12;
+        let width = match read16(tiff, entry + 2)? {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8 => 2,
+            4 | 9 | 11 => 4,
+            _ => 8,
+        };
+        let size = width * read32(tiff, entry + 4)?;
+        if size > 4
+            && let Some(offset) = read32(tiff, entry + 8)
+            && let Some(values) = tiff.get_mut(offset..offset + size)
+        {
+            values.fill(0);
+        }
+        tiff.get_mut(entry..entry + 12)?.fill(0);
+    }
+    tiff.get_mut(gps..gps + 2)?.fill(0);
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::{has_gps, read_exif_bytes, xmp_has_gps};
+
+    // Where the GPS IFD and its values sit in the TIFF block of `jpeg_with_gps`
+    const GPS_IFD: usize = 38;
+    const TIFF_LEN: usize = 92;
+    // SOI, then the APP1 marker, its length and the "Exif\0\0" header
+    const TIFF_START: usize = 2 + 4 + 6;
+
+    /// A JPEG whose EXIF has a camera make in IFD0 and a latitude in the GPS IFD.
+    fn jpeg_with_gps(little: bool) -> Vec<u8> {
+        let mut tiff = Vec::new();
+        let u16 = |t: &mut Vec<u8>, v: u16| t.extend(if little { v.to_le_bytes() } else { v.to_be_bytes() });
+        let u32 = |t: &mut Vec<u8>, v: u32| t.extend(if little { v.to_le_bytes() } else { v.to_be_bytes() });
+        tiff.extend(if little { b"II" } else { b"MM" });
+        u16(&mut tiff, 42);
+        u32(&mut tiff, 8);
+        // IFD0: Make "abc" inline, then the pointer to the GPS IFD
+        u16(&mut tiff, 2);
+        u16(&mut tiff, 0x010F);
+        u16(&mut tiff, 2);
+        u32(&mut tiff, 4);
+        tiff.extend(b"abc\0");
+        u16(&mut tiff, 0x8825);
+        u16(&mut tiff, 4);
+        u32(&mut tiff, 1);
+        u32(&mut tiff, GPS_IFD as u32);
+        u32(&mut tiff, 0);
+        // GPS IFD: GPSLatitudeRef "N" inline, GPSLatitude as three rationals after it
+        assert_eq!(tiff.len(), GPS_IFD);
+        u16(&mut tiff, 2);
+        u16(&mut tiff, 0x0001);
+        u16(&mut tiff, 2);
+        u32(&mut tiff, 2);
+        tiff.extend(b"N\0\0\0");
+        u16(&mut tiff, 0x0002);
+        u16(&mut tiff, 5);
+        u32(&mut tiff, 3);
+        u32(&mut tiff, (GPS_IFD + 30) as u32);
+        u32(&mut tiff, 0);
+        for value in [52, 1, 31, 1, 12, 1] {
+            u32(&mut tiff, value);
+        }
+        assert_eq!(tiff.len(), TIFF_LEN);
+
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        jpeg.extend(((2 + 6 + tiff.len()) as u16).to_be_bytes());
+        jpeg.extend(b"Exif\0\0");
+        jpeg.extend(tiff);
+        // A quantization table and the start of the image data, which must not be touched
+        jpeg.extend([0xFF, 0xDB, 0x00, 0x04, 0x12, 0x34]);
+        jpeg.extend([0xFF, 0xDA, 0x00, 0x02, 0x88, 0x25, 0xFF, 0xD9]);
+        jpeg
+    }
+
+    fn strips_gps(little: bool) {
+        let original = jpeg_with_gps(little);
+        assert!(read_exif_bytes(&original).is_some_and(|exif| has_gps(&exif)));
+
+        let mut stripped = original.clone();
+        assert!(strip_gps(&mut stripped));
+        assert_eq!(stripped.len(), original.len());
+        let gps = TIFF_START + GPS_IFD..TIFF_START + TIFF_LEN;
+        assert!(stripped[gps.clone()].iter().all(|&b| b == 0));
+        assert_eq!(stripped[..gps.start], original[..gps.start]);
+        assert_eq!(stripped[gps.end..], original[gps.end..]);
+
+        let exif = read_exif_bytes(&stripped).unwrap();
+        assert!(!has_gps(&exif));
+        assert!(exif.get_field(exif::Tag::Make, exif::In::PRIMARY).is_some());
+    }
+
+    #[test]
+    fn strips_gps_little_endian() {
+        strips_gps(true);
+    }
+
+    #[test]
+    fn strips_gps_big_endian() {
+        strips_gps(false);
+    }
+
+    #[test]
+    fn downscales_upright() {
+        // 40x20 pixels, meant to be shown turned 90 degrees clockwise (orientation 6)
+        let mut pixels = Vec::new();
+        JpegEncoder::new(&mut pixels).encode_image(&DynamicImage::new_rgb8(40, 20)).unwrap();
+        let tiff = [b"MM\0\x2a\0\0\0\x08\0\x01".as_slice(), b"\x01\x12\0\x03\0\0\0\x01\0\x06\0\0", b"\0\0\0\0"].concat();
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        jpeg.extend(((2 + 6 + tiff.len()) as u16).to_be_bytes());
+        jpeg.extend(b"Exif\0\0");
+        jpeg.extend(tiff);
+        jpeg.extend(&pixels[2..]);
+
+        let downscale = Downscale { max_dimension: Some(20), max_bytes: None, quality: 90 };
+        let out = downscale.apply(&jpeg).unwrap().unwrap();
+        let img = image::load_from_memory(&out).unwrap();
+        assert_eq!((img.width(), img.height()), (10, 20));
+    }
+
+    #[test]
+    fn strips_xmp_gps() {
+        let xmp = concat!(
+            r#"<x:xmpmeta><rdf:RDF><rdf:Description xmp:Rating="3" exif:GPSLatitude="52,31.2N" exif:GPSVersionID='2.2.0.0'>"#,
+            "<exif:GPSLongitude>13,24.6E</exif:GPSLongitude></rdf:Description></rdf:RDF></x:xmpmeta>",
+        );
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        jpeg.extend(((2 + XMP_HEADER.len() + xmp.len()) as u16).to_be_bytes());
+        jpeg.extend(XMP_HEADER);
+        jpeg.extend(xmp.as_bytes());
+        jpeg.extend([0xFF, 0xDA, 0x00, 0x02, 0x88, 0x25, 0xFF, 0xD9]);
+        assert!(read_exif_bytes(&jpeg).is_none());
+        assert!(xmp_has_gps(&jpeg));
+
+        let original = jpeg.clone();
+        assert!(strip_gps(&mut jpeg));
+        assert_eq!(jpeg.len(), original.len());
+        assert!(!xmp_has_gps(&jpeg));
+        let text = String::from_utf8_lossy(&jpeg);
+        assert!(text.contains(r#"xmp:Rating="3" exif:GPSLatitude="        " exif:GPSVersionID='       '>"#));
+        assert!(text.contains("<exif:GPSLongitude>        </exif:GPSLongitude>"));
+        assert!(!strip_gps(&mut jpeg));
+    }
+
+    #[test]
+    fn leaves_files_without_gps_alone() {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        assert!(!strip_gps(&mut png));
+        let mut plain = vec![0xFF, 0xD8, 0xFF, 0xDB, 0x00, 0x04, 0x12, 0x34, 0xFF, 0xD9];
+        let original = plain.clone();
+        assert!(!strip_gps(&mut plain));
+        assert_eq!(plain, original);
+    }
+}
@@ -0,0 +1,85 @@
+use crate::api::{
+    DEVICE_ID, TrashedAsset, delete_permanently, empty_trash, get_active_url, list_trashed, restore_all_trash, restore_from_trash,
+    validate_key,
+};
+use crate::config::Config;
+use anyhow::{Result, bail};
+use reqwest::Client;
+use std::io::{BufRead, Write};
+
+/// The server to talk to, with a checked key; the album doesn't matter here.
+async fn connect(client: &Client, config: &Config) -> Result<String> {
+    let Some(base_url) = get_active_url(client, &config.local_url, &config.ext_url).await else {
+        bail!("Could not connect to any Immich instance.");
+    };
+    validate_key(client, &base_url, &config.api_key).await?;
+    Ok(base_url)
+}
+
+/// Trashed assets this tool uploaded, or everything in the trash with `all`.
+async fn trashed(client: &Client, config: &Config, base_url: &str, all: bool) -> Result<Vec<TrashedAsset>> {
+    let mut assets = list_trashed(client, base_url, &config.api_key).await?;
+    assets.retain(|a| all || a.device_id == DEVICE_ID);
+    Ok(assets)
+}
+
+/// `trash list`: prints ID and original filename per trashed asset.
+pub async fn list(client: &Client, config: &Config, all: bool) -> Result<()> {
+    let base_url = connect(client, config).await?;
+    let assets = trashed(client, config, &base_url, all).await?;
+    for asset in &assets {
+        println!("{}\t{}", asset.id, asset.original_file_name);
+    }
+    println!("\n{} asset(s) in the trash{}", assets.len(), if all { "" } else { " uploaded by this tool (--all for every one)" });
+    Ok(())
+}
+
+/// `trash restore`: the given IDs, else what `trash list` shows (everything with `all`).
+pub async fn restore(client: &Client, config: &Config, ids: &[String], all: bool) -> Result<()> {
+    let base_url = connect(client, config).await?;
+    let key = &config.api_key;
+    if all {
+        restore_all_trash(client, &base_url, key).await?;
+        println!("Restored everything in the trash");
+        return Ok(());
+    }
+    let ids = match ids {
+        [] => trashed(client, config, &base_url, false).await?.into_iter().map(|a| a.id).collect(),
+        ids => ids.to_vec(),
+    };
+    if !ids.is_empty() {
+        restore_from_trash(client, &base_url, key, &ids).await?;
+    }
+    println!("Restored {} asset(s)", ids.len());
+    Ok(())
+}
+
+/// `trash empty`: permanently deletes what `trash list` shows, after asking unless `yes`.
+pub async fn empty(client: &Client, config: &Config, all: bool, yes: bool) -> Result<()> {
+    let base_url = connect(client, config).await?;
+    let key = &config.api_key;
+    let ids: Vec<String> = trashed(client, config, &base_url, all).await?.into_iter().map(|a| a.id).collect();
+    if ids.is_empty() {
+        println!("Nothing to delete");
+        return Ok(());
+    }
+    if !yes && !confirm(&format!("Permanently delete {} asset(s) from {}?", ids.len(), base_url))? {
+        println!("Nothing deleted");
+        return Ok(());
+    }
+    if all {
+        empty_trash(client, &base_url, key).await?;
+    } else {
+        delete_permanently(client, &base_url, key, &ids).await?;
+    }
+    println!("Deleted {} asset(s)", ids.len());
+    Ok(())
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    eprint!("{} [y/N] ", question);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
@@ -0,0 +1,72 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+
+/// Reads file paths, one per line, from a FIFO and forwards those inside one of `roots`
+/// for immediate upload, e.g. `echo ~/Pictures/Screenshots/shot.png > immich_sync.fifo`.
+/// Relative paths are taken relative to the first root.
+#[cfg(unix)]
+pub fn spawn_fifo_listener(fifo: &Path, roots: &[PathBuf], tx: mpsc::Sender<PathBuf>) -> Result<()> {
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::metadata(fifo) {
+        Ok(m) if m.file_type().is_fifo() => {}
+        Ok(_) => anyhow::bail!("{} exists and is not a FIFO", fifo.display()),
+        Err(_) => {
+            let c_path = std::ffi::CString::new(fifo.as_os_str().as_bytes())?;
+            // SAFETY: c_path is a valid NUL-terminated string for the duration of the call
+            if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+                return Err(std::io::Error::last_os_error()).with_context(|| format!("Failed to create FIFO {}", fifo.display()));
+            }
+        }
+    }
+    info!("Trigger FIFO listening at {}", fifo.display());
+
+    // Each root as configured and resolved: paths are checked against the resolved one
+    // but sent under the configured one, which is what jobs and history paths go by
+    let roots: Vec<(PathBuf, PathBuf)> = roots.iter().filter_map(|r| Some((r.clone(), r.canonicalize().ok()?))).collect();
+    let Some(base) = roots.first().map(|(configured, _)| configured.clone()) else {
+        anyhow::bail!("None of the sync folders exist");
+    };
+    let fifo = fifo.to_path_buf();
+    // Blocking reads on a plain thread: opening a FIFO waits until a writer connects
+    std::thread::spawn(move || {
+        loop {
+            let file = match std::fs::File::open(&fifo) {
+                Ok(f) => f,
+                Err(e) => {
+                    warn!("Trigger FIFO closed: {}", e);
+                    return;
+                }
+            };
+            for line in BufReader::new(file).lines().map_while(Result::ok) {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                let requested = base.join(line);
+                let under_root = requested.canonicalize().ok().and_then(|path| {
+                    roots.iter().find_map(|(configured, resolved)| Some(configured.join(path.strip_prefix(resolved).ok()?)))
+                });
+                match under_root {
+                    Some(path) => {
+                        if tx.blocking_send(path).is_err() {
+                            return;
+                        }
+                    }
+                    None => warn!("Ignoring trigger for {} (missing or outside the sync folder)", line),
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn spawn_fifo_listener(_fifo: &Path, _roots: &[PathBuf], _tx: mpsc::Sender<PathBuf>) -> Result<()> {
+    warn!("The trigger FIFO is only available on Unix.");
+    Ok(())
+}